@@ -13,10 +13,12 @@ SYSTEMD_USER_DIR="${XDG_CONFIG_HOME:-$HOME/.config}/systemd/user"
 # Binaries in build output
 DAEMON_SRC="$BUILD_DIR/daemon"
 UI_SRC="$BUILD_DIR/ui"
+CLI_SRC="$BUILD_DIR/clippo"
 
 # Names we install as
 DAEMON_NAME="clippo_daemon"
 UI_NAME="clippo_ui"
+CLI_NAME="clippo"
 
 # Service file (expected next to this script)
 SERVICE_SRC="$SCRIPT_DIR/clippo_daemon.service"
@@ -57,6 +59,12 @@ if [ ! -f "$UI_SRC" ]; then
   exit 1
 fi
 
+if [ ! -f "$CLI_SRC" ]; then
+  echo "Error: 'clippo' binary not found at $CLI_SRC."
+  echo "Build first: cargo build --release"
+  exit 1
+fi
+
 if [ ! -f "$SERVICE_SRC" ]; then
   echo "Error: service file not found at $SERVICE_SRC."
   exit 1
@@ -73,10 +81,14 @@ install -m 755 "$DAEMON_SRC" "$BIN_DIR/$DAEMON_NAME"
 echo "Installing ui to $BIN_DIR/$UI_NAME..."
 install -m 755 "$UI_SRC" "$BIN_DIR/$UI_NAME"
 
+echo "Installing command line client to $BIN_DIR/$CLI_NAME..."
+install -m 755 "$CLI_SRC" "$BIN_DIR/$CLI_NAME"
+
 # ---- convenience symlinks ----
 echo "Creating/Updating symlinks in $LINK_DIR..."
 ln -sf "$BIN_DIR/$DAEMON_NAME" "$LINK_DIR/$DAEMON_NAME"
 ln -sf "$BIN_DIR/$UI_NAME" "$LINK_DIR/$UI_NAME"
+ln -sf "$BIN_DIR/$CLI_NAME" "$LINK_DIR/$CLI_NAME"
 
 # ---- install user service ----
 echo "Installing user systemd service to $SERVICE_DST..."
@@ -97,6 +109,7 @@ echo
 echo "Installation complete."
 echo "Daemon status: systemctl --user status $SERVICE_NAME"
 echo "Run the UI with: $LINK_DIR/$UI_NAME"
+echo "Command line client: $LINK_DIR/$CLI_NAME help"
 if [[ ":$PATH:" != *":$LINK_DIR:"* ]]; then
   echo "Note: $LINK_DIR is not in PATH. Add it to run clippo_ui directly."
 fi
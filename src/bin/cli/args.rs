@@ -0,0 +1,59 @@
+//! Minimal command line parsing: commands take their flags and positional
+//! arguments from `Args`, then call `finish` to reject anything left over.
+
+use anyhow::{anyhow, Result};
+use std::collections::VecDeque;
+
+pub struct Args {
+    args: VecDeque<String>,
+}
+
+impl Args {
+    pub fn new(args: impl IntoIterator<Item = String>) -> Self {
+        Self {
+            args: args.into_iter().collect(),
+        }
+    }
+
+    /// Remove the flag `name`, ex: "--json". Returns whether it was given.
+    pub fn flag(&mut self, name: &str) -> bool {
+        let len = self.args.len();
+        self.args.retain(|arg| arg != name);
+        self.args.len() < len
+    }
+
+    /// Remove the option `name` and its value, ex: "--image file.png".
+    pub fn option(&mut self, name: &str) -> Result<Option<String>> {
+        let Some(idx) = self.args.iter().position(|arg| arg == name) else {
+            return Ok(None);
+        };
+        self.args.remove(idx);
+        self.args
+            .remove(idx)
+            .map(Some)
+            .ok_or_else(|| anyhow!("Missing value for {name}."))
+    }
+
+    /// Take the next positional argument, `what` describes it for the error message.
+    pub fn positional(&mut self, what: &str) -> Result<String> {
+        self.next_positional()
+            .ok_or_else(|| anyhow!("Missing {what}."))
+    }
+
+    /// Take the next positional argument if there is one.
+    pub fn next_positional(&mut self) -> Option<String> {
+        let idx = self
+            .args
+            .iter()
+            .position(|arg| arg == "-" || !arg.starts_with('-'))?;
+        self.args.remove(idx)
+    }
+
+    /// Fail on arguments no one asked for.
+    pub fn finish(self) -> Result<()> {
+        match self.args.front() {
+            Some(arg) => Err(anyhow!("Unexpected argument \"{arg}\".")),
+            None => Ok(()),
+        }
+    }
+}
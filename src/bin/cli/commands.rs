@@ -0,0 +1,532 @@
+//! The `clippo` commands. Entries are selected by their position in `clippo list`
+//! (1 is the most recent) or by their stable id, written "id:42".
+
+use crate::args::Args;
+use crate::client::DaemonClient;
+use crate::history::{CaptureState, ClipboardHistoryEntry, HistoryItem};
+use crate::protocol::Event;
+use crate::protocol::{DaemonStatus, EntrySelector, Request, Response};
+use crate::util::{format_duration, format_size, unix_now};
+use crate::{base64, image};
+
+use anyhow::{anyhow, Context, Result};
+use serde::Serialize;
+use std::env;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufReader, Read, Write};
+
+/// Length of the previews printed by `list` and `search`.
+const PREVIEW_CHARS: usize = 72;
+/// Launchers show a single line per entry, but can scroll through it.
+const MENU_LABEL_CHARS: usize = 200;
+/// Largest base64 payload `osc52` writes by default, terminals drop or cut longer
+/// sequences, ex: xterm and hterm stop around 100 KB.
+const OSC52_DEFAULT_MAX_BYTES: usize = 100_000;
+
+/// An entry as printed by `--json`, without the image pixels unless asked for.
+#[derive(Serialize)]
+struct JsonEntry<'a> {
+    /// Not printed for history events, where only the id is meaningful.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    position: Option<usize>,
+    id: u64,
+    kind: &'static str,
+    /// `None` for images, and for sensitive text unless asked for.
+    text: Option<&'a str>,
+    width: Option<usize>,
+    height: Option<usize>,
+    size_bytes: usize,
+    captured_at: u64,
+    pinned: bool,
+    sensitive: bool,
+    expires_at: Option<u64>,
+    source_app: Option<&'a str>,
+    /// Base64 encoded PNG of images, only printed on request.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    image_png: Option<String>,
+}
+
+impl<'a> JsonEntry<'a> {
+    fn new(position: Option<usize>, item: &'a HistoryItem, show_sensitive: bool) -> Self {
+        let (kind, text, size) = match &item.entry {
+            ClipboardHistoryEntry::Text(text) => (
+                "text",
+                (!item.sensitive || show_sensitive).then_some(text.as_str()),
+                None,
+            ),
+            ClipboardHistoryEntry::Image(image) => {
+                ("image", None, Some((image.width, image.height)))
+            }
+        };
+
+        Self {
+            position,
+            id: item.id,
+            kind,
+            text,
+            width: size.map(|(width, _)| width),
+            height: size.map(|(_, height)| height),
+            size_bytes: item.entry.size_bytes(),
+            captured_at: item.captured_at,
+            pinned: item.pinned,
+            sensitive: item.sensitive,
+            expires_at: item.expires_at,
+            source_app: item.source_app.as_ref().map(|app| app.name()),
+            image_png: None,
+        }
+    }
+
+    /// Add the image of `item` as a PNG, its pixels are fetched from the daemon through
+    /// `client` since events only describe images by their size.
+    fn with_image(mut self, client: &mut DaemonClient, item: &HistoryItem) -> Result<Self> {
+        if let ClipboardHistoryEntry::Image(_) = &item.entry {
+            let ClipboardHistoryEntry::Image(image) = client.entry(item.id)?.entry else {
+                return Err(anyhow!("Entry id:{} is no longer an image.", item.id));
+            };
+            let mut png = Vec::new();
+            image::encode_png(&image, &mut png)?;
+            self.image_png = Some(base64::encode(&png));
+        }
+        Ok(self)
+    }
+}
+
+/// A history change as printed by `watch --json`.
+#[derive(Serialize)]
+struct JsonEvent<'a> {
+    /// "added", "updated", "removed", "cleared" or "stopping".
+    event: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    id: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    entry: Option<JsonEntry<'a>>,
+}
+
+/// Parse an entry argument: a position (1 is the most recent) or an id like "id:42".
+fn parse_selector(value: &str) -> Result<EntrySelector> {
+    if let Some(id) = value.strip_prefix("id:") {
+        let id = id
+            .parse()
+            .context(format!("\"{value}\" is not a valid entry id."))?;
+        return Ok(EntrySelector::Id(id));
+    }
+
+    match value.parse() {
+        Ok(position) if position > 0 => Ok(EntrySelector::Position(position)),
+        _ => Err(anyhow!(
+            "\"{value}\" is not a valid entry, expected a position (1 is the most recent) or an id like \"id:42\"."
+        )),
+    }
+}
+
+/// Resolve the entry argument of a command. The daemon looks it up, so the history
+/// is not listed for a single entry.
+fn select_entry(client: &mut DaemonClient, args: &mut Args) -> Result<HistoryItem> {
+    let selector = parse_selector(&args.positional("entry (position or id:ID)")?)?;
+    client.find_entry(selector)
+}
+
+/// Print entries with their position in the whole history, as text or JSON lines.
+fn print_entries<'a>(
+    entries: impl Iterator<Item = (usize, &'a HistoryItem)>,
+    json: bool,
+    show_sensitive: bool,
+) -> Result<()> {
+    let mut stdout = io::stdout().lock();
+    for (position, item) in entries {
+        if json {
+            let entry = JsonEntry::new(Some(position), item, show_sensitive);
+            writeln!(stdout, "{}", serde_json::to_string(&entry)?)?;
+            continue;
+        }
+
+        let pin = if item.pinned { "*" } else { " " };
+        writeln!(
+            stdout,
+            "{position:>3} {pin} id:{:<6} {}",
+            item.id,
+            item.preview(PREVIEW_CHARS, show_sensitive)
+        )?;
+    }
+
+    Ok(())
+}
+
+/// `list [--json] [--show-sensitive]`
+pub fn list(mut args: Args) -> Result<()> {
+    let json = args.flag("--json");
+    let show_sensitive = args.flag("--show-sensitive");
+    args.finish()?;
+
+    let history = DaemonClient::connect()?.history()?;
+    print_entries(
+        history
+            .iter()
+            .enumerate()
+            .map(|(idx, item)| (idx + 1, item)),
+        json,
+        show_sensitive,
+    )
+}
+
+/// `search <query> [--json] [--show-sensitive]`, sensitive entries are only searched when shown.
+pub fn search(mut args: Args) -> Result<()> {
+    let json = args.flag("--json");
+    let show_sensitive = args.flag("--show-sensitive");
+    let mut words = vec![args.positional("search query")?];
+    words.extend(std::iter::from_fn(|| args.next_positional()));
+    args.finish()?;
+
+    let query = words.join(" ");
+    let history = DaemonClient::connect()?.history()?;
+    print_entries(
+        history
+            .iter()
+            .enumerate()
+            .map(|(idx, item)| (idx + 1, item))
+            .filter(|(_, item)| (!item.sensitive || show_sensitive) && item.matches(&query)),
+        json,
+        show_sensitive,
+    )
+}
+
+/// `get <entry>`, prints the text of an entry as is.
+pub fn get(mut args: Args) -> Result<()> {
+    let mut client = DaemonClient::connect()?;
+    let item = select_entry(&mut client, &mut args)?;
+    args.finish()?;
+
+    match item.entry {
+        ClipboardHistoryEntry::Text(text) => {
+            let mut stdout = io::stdout().lock();
+            stdout.write_all(text.as_bytes())?;
+            stdout.flush()?;
+            Ok(())
+        }
+        ClipboardHistoryEntry::Image(image) => Err(anyhow!(
+            "Entry id:{} is an image ({}x{}), use \"clippo copy\" to put it on the clipboard.",
+            item.id,
+            image.width,
+            image.height
+        )),
+    }
+}
+
+/// `copy <entry>`, the daemon puts the entry on the clipboard and keeps serving it.
+pub fn copy(mut args: Args) -> Result<()> {
+    let mut client = DaemonClient::connect()?;
+    let item = select_entry(&mut client, &mut args)?;
+    args.finish()?;
+
+    client.request_ok(Request::SetClipboard { id: item.id })
+}
+
+/// `add [--copy] [--image <file.png>]` records stdin, or the image, as a new entry
+/// without going through the clipboard. `--copy` also puts it on the clipboard.
+/// Ex: `git log -1 --format=%H | clippo add`
+pub fn add(mut args: Args) -> Result<()> {
+    let set_clipboard = args.flag("--copy");
+    let image = args.option("--image")?;
+    args.finish()?;
+
+    let entry = match image {
+        Some(path) => {
+            let file = File::open(&path).context(format!("Could not open {path}."))?;
+            let image = image::decode_png(BufReader::new(file))
+                .context(format!("Could not read the image {path}."))?;
+            ClipboardHistoryEntry::Image(image)
+        }
+        None => {
+            let mut text = String::new();
+            io::stdin()
+                .read_to_string(&mut text)
+                .context("Could not read text from stdin.")?;
+            if text.trim().is_empty() {
+                return Err(anyhow!("Nothing to add, stdin is empty."));
+            }
+            ClipboardHistoryEntry::Text(text)
+        }
+    };
+
+    let request = Request::AddEntry {
+        entry,
+        set_clipboard,
+        explicit: true,
+    };
+    match DaemonClient::connect()?.request(request)? {
+        Response::Added { id: Some(_) } => Ok(()),
+        // Not an error for scripts, but say why it is missing from the history
+        Response::Added { id: None } => {
+            eprintln!("Not added to the history: too large, matched a secret rule, or pinned entries leave no room.");
+            Ok(())
+        }
+        response => Err(anyhow!("Unexpected response to add request: {response:?}")),
+    }
+}
+
+/// `osc52 <entry> [--tmux | --no-tmux] [--max-bytes <n>]` writes the text of an entry as an
+/// OSC 52 escape sequence, so the terminal puts it on its own clipboard, ex: over SSH.
+/// Inside tmux, given away by `TMUX`, the sequence is wrapped for passthrough.
+pub fn osc52(mut args: Args) -> Result<()> {
+    let force_tmux = args.flag("--tmux");
+    let no_tmux = args.flag("--no-tmux");
+    let max_bytes = match args.option("--max-bytes")? {
+        Some(value) => value
+            .parse()
+            .context(format!("\"{value}\" is not a valid number of bytes."))?,
+        None => OSC52_DEFAULT_MAX_BYTES,
+    };
+    if force_tmux && no_tmux {
+        return Err(anyhow!("--tmux and --no-tmux can not be used together."));
+    }
+
+    let mut client = DaemonClient::connect()?;
+    let item = select_entry(&mut client, &mut args)?;
+    args.finish()?;
+
+    let ClipboardHistoryEntry::Text(text) = &item.entry else {
+        return Err(anyhow!(
+            "Entry id:{} is an image, OSC 52 only carries text.",
+            item.id
+        ));
+    };
+    let payload = base64::encode(text.as_bytes());
+    if payload.len() > max_bytes {
+        return Err(anyhow!(
+            "Entry id:{} is {} once encoded, above the limit of {}, see --max-bytes.",
+            item.id,
+            format_size(payload.len()),
+            format_size(max_bytes)
+        ));
+    }
+
+    let tmux = force_tmux || (!no_tmux && env::var_os("TMUX").is_some());
+    let mut terminal = terminal();
+    terminal.write_all(osc52_sequence(&payload, tmux).as_bytes())?;
+    terminal.flush()?;
+    Ok(())
+}
+
+fn osc52_sequence(payload: &str, tmux: bool) -> String {
+    // "c" targets the clipboard, BEL ends the sequence
+    let sequence = format!("\x1b]52;c;{payload}\x07");
+    if !tmux {
+        return sequence;
+    }
+
+    // tmux hands the content of this DCS sequence to the outer terminal as is,
+    // escape characters inside it are doubled
+    format!("\x1bPtmux;{}\x1b\\", sequence.replace('\x1b', "\x1b\x1b"))
+}
+
+/// The controlling terminal, so the sequence reaches it even if stdout is redirected.
+fn terminal() -> Box<dyn Write> {
+    match OpenOptions::new().write(true).open("/dev/tty") {
+        Ok(tty) => Box::new(tty),
+        Err(_) => Box::new(io::stdout()),
+    }
+}
+
+/// `pick [--show-sensitive]` prints one line per entry for dmenu, rofi or fzf:
+/// the id of the entry, a tab, then its label.
+/// `pick --select` reads the line chosen in the launcher from stdin and copies that entry.
+/// Ex: `clippo pick | rofi -dmenu | clippo pick --select`
+pub fn pick(mut args: Args) -> Result<()> {
+    let select = args.flag("--select");
+    let show_sensitive = args.flag("--show-sensitive");
+    args.finish()?;
+
+    if select {
+        return select_picked();
+    }
+
+    // Labels need the text of every entry, images are only listed by their size
+    let history = DaemonClient::connect()?.history()?;
+    let mut stdout = io::stdout().lock();
+    for item in &history {
+        writeln!(
+            stdout,
+            "{}\t{}",
+            item.id,
+            item.menu_label(MENU_LABEL_CHARS, show_sensitive)
+        )?;
+    }
+    Ok(())
+}
+
+fn select_picked() -> Result<()> {
+    let mut line = String::new();
+    io::stdin()
+        .read_line(&mut line)
+        .context("Could not read the selection from stdin.")?;
+
+    // Nothing selected, ex: the launcher was closed with Escape
+    let line = line.trim();
+    if line.is_empty() {
+        return Ok(());
+    }
+
+    let id = line
+        .split(|char: char| char == '\t' || char.is_whitespace())
+        .next()
+        .and_then(|id| id.parse().ok())
+        .ok_or_else(|| anyhow!("\"{line}\" does not start with an entry id."))?;
+    DaemonClient::connect()?.request_ok(Request::SetClipboard { id })
+}
+
+/// `watch [--json] [--include-images] [--show-sensitive]` prints history changes as they
+/// happen, until the daemon stops. With `--json`, one object per line.
+pub fn watch(mut args: Args) -> Result<()> {
+    let json = args.flag("--json");
+    let include_images = args.flag("--include-images");
+    let show_sensitive = args.flag("--show-sensitive");
+    args.finish()?;
+    if include_images && !json {
+        return Err(anyhow!("--include-images only applies to --json output."));
+    }
+
+    let mut client = DaemonClient::connect()?;
+    client.subscribe()?;
+    // The subscribed connection only carries events, images are fetched on another one
+    let mut images_client = if include_images {
+        Some(DaemonClient::connect()?)
+    } else {
+        None
+    };
+
+    let mut stdout = io::stdout().lock();
+    while let Some(event) = client.next_event()? {
+        let (name, id, item) = match &event {
+            Event::EntryAdded(item) => ("added", Some(item.id), Some(item)),
+            Event::Updated(item) => ("updated", Some(item.id), Some(item)),
+            Event::EntryRemoved { id } => ("removed", Some(*id), None),
+            Event::Cleared => ("cleared", None, None),
+            Event::Stopping => ("stopping", None, None),
+        };
+
+        if json {
+            let entry = match (item, images_client.as_mut()) {
+                (Some(item), Some(images_client)) => {
+                    let entry = JsonEntry::new(None, item, show_sensitive);
+                    // The entry may already be gone, ex: evicted right after being added
+                    match entry.with_image(images_client, item) {
+                        Ok(entry) => Some(entry),
+                        Err(e) => {
+                            eprintln!("Could not get the image of entry id:{}: {e:#}", item.id);
+                            Some(JsonEntry::new(None, item, show_sensitive))
+                        }
+                    }
+                }
+                (Some(item), None) => Some(JsonEntry::new(None, item, show_sensitive)),
+                (None, _) => None,
+            };
+            let event = JsonEvent {
+                event: name,
+                id,
+                entry,
+            };
+            writeln!(stdout, "{}", serde_json::to_string(&event)?)?;
+        } else {
+            match (id, item) {
+                (_, Some(item)) => writeln!(
+                    stdout,
+                    "{name:<8} id:{:<6} {}",
+                    item.id,
+                    item.preview(PREVIEW_CHARS, show_sensitive)
+                )?,
+                (Some(id), None) => writeln!(stdout, "{name:<8} id:{id}")?,
+                (None, None) => writeln!(stdout, "{name}")?,
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// `delete <entry>`
+pub fn delete(mut args: Args) -> Result<()> {
+    let mut client = DaemonClient::connect()?;
+    let item = select_entry(&mut client, &mut args)?;
+    args.finish()?;
+
+    client.request_ok(Request::DeleteEntry { id: item.id })
+}
+
+/// `pin <entry>` and `unpin <entry>`, pinned entries are never evicted.
+pub fn pin(mut args: Args, pinned: bool) -> Result<()> {
+    let mut client = DaemonClient::connect()?;
+    let item = select_entry(&mut client, &mut args)?;
+    args.finish()?;
+
+    client.request_ok(Request::SetPinned {
+        id: item.id,
+        pinned,
+    })
+}
+
+/// `clear`
+pub fn clear(args: Args) -> Result<()> {
+    args.finish()?;
+
+    DaemonClient::connect()?.request_ok(Request::ResetHistory)
+}
+
+/// `status [--json]`
+pub fn status(mut args: Args) -> Result<()> {
+    let json = args.flag("--json");
+    args.finish()?;
+
+    let status = match DaemonClient::connect()?.request(Request::GetStatus)? {
+        Response::Status(status) => status,
+        response => {
+            return Err(anyhow!(
+                "Unexpected response to status request: {response:?}"
+            ))
+        }
+    };
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&status)?);
+    } else {
+        print_status(&status);
+    }
+    Ok(())
+}
+
+fn print_status(status: &DaemonStatus) {
+    let now = unix_now();
+    let capture = match status.capture_state {
+        CaptureState::Recording => "recording".to_string(),
+        CaptureState::Paused(None) => "paused".to_string(),
+        CaptureState::Paused(Some(until)) => {
+            format!("paused for {}", format_duration(until.saturating_sub(now)))
+        }
+    };
+    let last_capture = match status.last_capture_at {
+        Some(at) => format!("{} ago", format_duration(now.saturating_sub(at))),
+        None => "none since start".to_string(),
+    };
+
+    println!("Clippo daemon {}, {capture}", status.version);
+    println!("Uptime:       {}", format_duration(status.uptime_secs));
+    println!("Backend:      {}", status.backend);
+    println!(
+        "History:      {} text and {} image entries, {}",
+        status.text_entries,
+        status.image_entries,
+        format_size(status.total_bytes)
+    );
+    println!("Last capture: {last_capture}");
+    println!("Saved to:     {}", status.history_path);
+
+    if !status.recent_errors.is_empty() {
+        println!("Recent errors:");
+        for error in &status.recent_errors {
+            println!(
+                "  {} ago: {}",
+                format_duration(now.saturating_sub(error.at)),
+                error.message
+            );
+        }
+    }
+}
@@ -0,0 +1,446 @@
+//! Drop-in replacements for `xclip`, `wl-copy` and `wl-paste` going through the daemon,
+//! so editors and scripts calling them feed the history. Used when `clippo` is run
+//! through a link named after one of them, or as `clippo xclip ...`.
+//! Copies are recorded like `clippo add --copy`. Only the clipboard selection is handled,
+//! the daemon does not track the primary selection: it is handed over to the real command,
+//! found further in `PATH`.
+
+use crate::client::DaemonClient;
+use crate::history::ClipboardHistoryEntry;
+use crate::image::{self, PNG_SIGNATURE};
+use crate::protocol::{Request, Response};
+
+use anyhow::{anyhow, Context, Result};
+use std::env;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::path::PathBuf;
+use std::process::{self, Command};
+
+/// Targets offered for text, as listed by `xclip -t TARGETS -o` and `wl-paste -l`.
+const TEXT_TARGETS: &[&str] = &[
+    "text/plain;charset=utf-8",
+    "text/plain",
+    "UTF8_STRING",
+    "STRING",
+    "TEXT",
+];
+const IMAGE_TARGET: &str = "image/png";
+
+const XCLIP_USAGE: &str = "\
+Usage: xclip [-i | -o] -selection clipboard [-t <type>] [-f] [-r] [file ...]
+
+xclip compatible interface of Clippo, copies are recorded in the history.
+Types are text or image/png. Other selections, including the primary selection
+which is the default, are handed over to the real xclip.";
+
+const WL_COPY_USAGE: &str = "\
+Usage: wl-copy [-n] [-t <type>] [text ...]
+
+wl-copy compatible interface of Clippo, copies are recorded in the history.
+Copies stdin when no text is given, types are text or image/png.
+The primary selection is handed over to the real wl-copy.";
+
+const WL_PASTE_USAGE: &str = "\
+Usage: wl-paste [-n] [-l] [-t <type>]
+
+wl-paste compatible interface of Clippo, pastes the current clipboard content.
+The primary selection is handed over to the real wl-paste.";
+
+/// The compatible command named `name`, if any.
+pub fn command(name: &str) -> Option<fn(Vec<String>) -> Result<()>> {
+    match name {
+        "xclip" => Some(xclip),
+        "wl-copy" => Some(wl_copy),
+        "wl-paste" => Some(wl_paste),
+        _ => None,
+    }
+}
+
+/// What a client asked to copy or paste.
+#[derive(Clone, Copy, PartialEq)]
+enum ContentType {
+    Text,
+    Png,
+}
+
+impl ContentType {
+    fn parse(mime: &str) -> Result<Self> {
+        match mime {
+            IMAGE_TARGET => Ok(ContentType::Png),
+            mime if mime.starts_with("text") || TEXT_TARGETS.contains(&mime) => {
+                Ok(ContentType::Text)
+            }
+            mime => Err(anyhow!(
+                "Unsupported type \"{mime}\", Clippo only stores text and {IMAGE_TARGET}."
+            )),
+        }
+    }
+}
+
+/// Run the real `name` command with `args`, for the primary selection which the daemon
+/// does not track, ex: Neovim copies to it with `clipboard=unnamed` or the `"*` register.
+/// Exits with the status of the command if it fails.
+fn delegate_primary(name: &str, args: Vec<String>) -> Result<()> {
+    let real_command = real_command(name).ok_or_else(|| {
+        anyhow!(
+            "Clippo only handles the clipboard selection, and no other {name} was found in PATH for the primary selection."
+        )
+    })?;
+
+    let status = Command::new(&real_command)
+        .args(args)
+        .status()
+        .context(format!("Could not run {}.", real_command.display()))?;
+    if !status.success() {
+        process::exit(status.code().unwrap_or(1));
+    }
+    Ok(())
+}
+
+/// The first `name` in `PATH` which is not Clippo, ex: through a link named after it.
+fn real_command(name: &str) -> Option<PathBuf> {
+    let clippo = env::current_exe().ok()?.canonicalize().ok()?;
+    env::split_paths(&env::var_os("PATH")?)
+        .map(|dir| dir.join(name))
+        .filter(|path| path.is_file())
+        .find(|path| path.canonicalize().is_ok_and(|path| path != clippo))
+}
+
+/// `xclip [-i | -o] -selection clipboard [-t <type>] [-f] [-r] [file ...]`
+/// Like xclip, options can be abbreviated, ex: `-sel c`.
+fn xclip(args: Vec<String>) -> Result<()> {
+    let original_args = args.clone();
+    let mut output = false;
+    let mut clipboard_selection = false;
+    let mut target = None;
+    let mut filter = false;
+    let mut trim_newline = false;
+    let mut files = Vec::new();
+
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        let Some(name) = arg.strip_prefix('-').filter(|name| !name.is_empty()) else {
+            files.push(arg);
+            continue;
+        };
+        let is = |option: &str, min_len: usize| name.len() >= min_len && option.starts_with(name);
+        let mut value = || {
+            args.next()
+                .ok_or_else(|| anyhow!("Missing value for {arg}."))
+        };
+
+        if name == "version" {
+            println!("clippo {} (xclip compatible)", env!("CARGO_PKG_VERSION"));
+            return Ok(());
+        } else if is("help", 1) {
+            println!("{XCLIP_USAGE}");
+            return Ok(());
+        } else if is("in", 1) {
+            output = false;
+        } else if is("out", 1) {
+            output = true;
+        } else if is("selection", 2) {
+            // xclip only looks at the first letter: "c", "clip" and "clipboard" all work
+            clipboard_selection = value()?.starts_with('c');
+        } else if is("target", 1) {
+            target = Some(value()?);
+        } else if is("filter", 1) {
+            filter = true;
+        } else if is("rmlastnl", 1) {
+            trim_newline = true;
+        } else if is("loops", 1) || is("display", 1) {
+            // The daemon owns the clipboard and the X connection
+            value()?;
+        } else if is("quiet", 1) || is("silent", 2) || is("verbose", 1) || is("noutf8", 1) {
+            // Clippo never forks nor prints progress
+        } else {
+            return Err(anyhow!("Unknown option \"{arg}\".\n\n{XCLIP_USAGE}"));
+        }
+    }
+
+    // The primary selection is the xclip default
+    if !clipboard_selection {
+        return delegate_primary("xclip", original_args);
+    }
+
+    if output {
+        if !files.is_empty() {
+            return Err(anyhow!("Files can not be given with -o."));
+        }
+        return match target.as_deref() {
+            Some("TARGETS") => list_targets(true),
+            Some(target) => paste(Some(ContentType::parse(target)?), trim_newline),
+            // xclip asks for UTF8_STRING by default, images need an explicit target
+            None => paste(Some(ContentType::Text), trim_newline),
+        };
+    }
+
+    let content_type = target.as_deref().map(ContentType::parse).transpose()?;
+    let mut content = if files.is_empty() {
+        read_stdin()?
+    } else {
+        let mut content = Vec::new();
+        for file in &files {
+            content.extend(fs::read(file).context(format!("Could not read {file}."))?);
+        }
+        content
+    };
+    if filter {
+        io::stdout().lock().write_all(&content)?;
+    }
+    if trim_newline && content.ends_with(b"\n") {
+        content.pop();
+    }
+    copy(content, content_type.or(Some(ContentType::Text)))
+}
+
+/// `wl-copy [-n] [-t <type>] [text ...]`
+fn wl_copy(args: Vec<String>) -> Result<()> {
+    let original_args = args.clone();
+    let mut trim_newline = false;
+    let mut content_type = None;
+    let mut text = Vec::new();
+
+    let mut args = expand_short_options(args, "ts").into_iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--" => {
+                text.extend(args.by_ref());
+                break;
+            }
+            "-h" | "--help" => {
+                println!("{WL_COPY_USAGE}");
+                return Ok(());
+            }
+            "-v" | "--version" => {
+                println!("clippo {} (wl-copy compatible)", env!("CARGO_PKG_VERSION"));
+                return Ok(());
+            }
+            "-p" | "--primary" => return delegate_primary("wl-copy", original_args),
+            "-n" | "--trim-newline" => trim_newline = true,
+            "-t" | "--type" => {
+                content_type = Some(ContentType::parse(&option_value(&mut args, &arg)?)?)
+            }
+            "-s" | "--seat" => {
+                option_value(&mut args, &arg)?;
+            }
+            // The daemon keeps serving the clipboard, it never has to run in the foreground
+            "-o" | "--paste-once" | "-f" | "--foreground" | "-r" | "--regular" => {}
+            "-c" | "--clear" => {
+                return Err(anyhow!(
+                    "Clearing the clipboard is not supported by Clippo."
+                ));
+            }
+            arg if arg.starts_with('-') && arg != "-" => {
+                return Err(anyhow!("Unknown option \"{arg}\".\n\n{WL_COPY_USAGE}"));
+            }
+            _ => text.push(arg),
+        }
+    }
+
+    let mut content = if text.is_empty() {
+        read_stdin()?
+    } else {
+        text.join(" ").into_bytes()
+    };
+    if trim_newline && content.ends_with(b"\n") {
+        content.pop();
+    }
+    copy(content, content_type)
+}
+
+/// `wl-paste [-n] [-l] [-t <type>]`
+fn wl_paste(args: Vec<String>) -> Result<()> {
+    let original_args = args.clone();
+    let mut no_newline = false;
+    let mut list_types = false;
+    let mut content_type = None;
+
+    let mut args = expand_short_options(args, "tsw").into_iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-h" | "--help" => {
+                println!("{WL_PASTE_USAGE}");
+                return Ok(());
+            }
+            "-v" | "--version" => {
+                println!("clippo {} (wl-paste compatible)", env!("CARGO_PKG_VERSION"));
+                return Ok(());
+            }
+            "-p" | "--primary" => return delegate_primary("wl-paste", original_args),
+            "-n" | "--no-newline" => no_newline = true,
+            "-l" | "--list-types" => list_types = true,
+            "-t" | "--type" => {
+                content_type = Some(ContentType::parse(&option_value(&mut args, &arg)?)?)
+            }
+            "-s" | "--seat" => {
+                option_value(&mut args, &arg)?;
+            }
+            "-w" | "--watch" => {
+                return Err(anyhow!(
+                    "Watching the clipboard is not supported by Clippo."
+                ));
+            }
+            arg => {
+                return Err(anyhow!(
+                    "Unexpected argument \"{arg}\".\n\n{WL_PASTE_USAGE}"
+                ))
+            }
+        }
+    }
+
+    if list_types {
+        return list_targets(false);
+    }
+    // wl-paste ends text with a newline unless asked not to
+    let entry = paste_entry(content_type)?;
+    let mut stdout = io::stdout().lock();
+    write_entry(&entry, &mut stdout)?;
+    if matches!(entry, ClipboardHistoryEntry::Text(_)) && !no_newline {
+        stdout.write_all(b"\n")?;
+    }
+    stdout.flush()?;
+    Ok(())
+}
+
+/// Split grouped short options, ex: "-nt" into "-n" "-t", and "--type=text" into
+/// "--type" "text". `value_options` are the short options taking a value, which is the
+/// rest of the group if any, ex: "-ttext/plain".
+fn expand_short_options(args: Vec<String>, value_options: &str) -> Vec<String> {
+    let mut expanded = Vec::new();
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        if arg == "--" {
+            expanded.push(arg);
+            expanded.extend(args.by_ref());
+            break;
+        }
+
+        if let Some(long) = arg.strip_prefix("--") {
+            match long.split_once('=') {
+                Some((name, value)) => expanded.extend([format!("--{name}"), value.to_string()]),
+                None => expanded.push(arg),
+            }
+            continue;
+        }
+
+        match arg.strip_prefix('-') {
+            Some(group) if group.chars().count() > 1 => {
+                for (idx, option) in group.char_indices() {
+                    expanded.push(format!("-{option}"));
+                    let rest = &group[idx + option.len_utf8()..];
+                    if value_options.contains(option) && !rest.is_empty() {
+                        expanded.push(rest.to_string());
+                        break;
+                    }
+                }
+            }
+            _ => expanded.push(arg),
+        }
+    }
+    expanded
+}
+
+fn option_value(args: &mut impl Iterator<Item = String>, option: &str) -> Result<String> {
+    args.next()
+        .ok_or_else(|| anyhow!("Missing value for {option}."))
+}
+
+fn read_stdin() -> Result<Vec<u8>> {
+    let mut content = Vec::new();
+    io::stdin()
+        .read_to_end(&mut content)
+        .context("Could not read stdin.")?;
+    Ok(content)
+}
+
+/// Put `content` on the clipboard, the daemon records it unless it is blank, ex: an empty
+/// line yanked in an editor. Without a type, PNG images are recognized by their signature
+/// and anything else is text.
+fn copy(content: Vec<u8>, content_type: Option<ContentType>) -> Result<()> {
+    let content_type = content_type.unwrap_or(if content.starts_with(PNG_SIGNATURE) {
+        ContentType::Png
+    } else {
+        ContentType::Text
+    });
+
+    let entry = match content_type {
+        ContentType::Png => ClipboardHistoryEntry::Image(image::decode_png(content.as_slice())?),
+        ContentType::Text => {
+            let text = String::from_utf8(content).context("Text to copy is not valid UTF-8.")?;
+            ClipboardHistoryEntry::Text(text)
+        }
+    };
+
+    // Left out of the history while capture is paused, like a copy from any other program
+    let request = Request::AddEntry {
+        entry,
+        set_clipboard: true,
+        explicit: false,
+    };
+    match DaemonClient::connect()?.request(request)? {
+        // On the clipboard even if not recorded, ex: too large, blank or capture paused
+        Response::Added { .. } => Ok(()),
+        response => Err(anyhow!("Unexpected response to add request: {response:?}")),
+    }
+}
+
+/// The clipboard content, failing if it is empty or not of `content_type`.
+fn paste_entry(content_type: Option<ContentType>) -> Result<ClipboardHistoryEntry> {
+    let entry = DaemonClient::connect()?
+        .clipboard()?
+        .ok_or_else(|| anyhow!("Nothing is copied."))?;
+
+    match (&entry, content_type) {
+        (ClipboardHistoryEntry::Text(_), Some(ContentType::Png)) => {
+            Err(anyhow!("The clipboard holds text, not {IMAGE_TARGET}."))
+        }
+        (ClipboardHistoryEntry::Image(_), Some(ContentType::Text)) => Err(anyhow!(
+            "The clipboard holds an image, ask for the {IMAGE_TARGET} type."
+        )),
+        _ => Ok(entry),
+    }
+}
+
+fn paste(content_type: Option<ContentType>, trim_newline: bool) -> Result<()> {
+    let mut entry = paste_entry(content_type)?;
+    if let ClipboardHistoryEntry::Text(text) = &mut entry {
+        if trim_newline && text.ends_with('\n') {
+            text.pop();
+        }
+    }
+
+    let mut stdout = io::stdout().lock();
+    write_entry(&entry, &mut stdout)?;
+    stdout.flush()?;
+    Ok(())
+}
+
+/// Write text as is and images as PNG.
+fn write_entry(entry: &ClipboardHistoryEntry, writer: &mut impl Write) -> Result<()> {
+    match entry {
+        ClipboardHistoryEntry::Text(text) => writer.write_all(text.as_bytes())?,
+        ClipboardHistoryEntry::Image(image) => image::encode_png(image, writer)?,
+    }
+    Ok(())
+}
+
+/// Print the types the clipboard content is offered as, one per line.
+/// X11 clients also expect the "TARGETS" target itself.
+fn list_targets(x11: bool) -> Result<()> {
+    let targets = match DaemonClient::connect()?.clipboard()? {
+        Some(ClipboardHistoryEntry::Text(_)) => TEXT_TARGETS,
+        Some(ClipboardHistoryEntry::Image(_)) => &[IMAGE_TARGET],
+        None => return Err(anyhow!("Nothing is copied.")),
+    };
+
+    let mut stdout = io::stdout().lock();
+    if x11 {
+        writeln!(stdout, "TARGETS")?;
+    }
+    for target in targets {
+        writeln!(stdout, "{target}")?;
+    }
+    Ok(())
+}
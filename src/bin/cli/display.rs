@@ -0,0 +1,44 @@
+//! How history entries are shown in a terminal.
+
+use crate::history::{ClipboardHistoryEntry, HistoryItem};
+
+impl HistoryItem {
+    /// Label for launchers reading one entry per line: control characters of the text
+    /// are escaped, ex: a newline becomes `\n`, and it is cut after `max_chars` characters.
+    pub fn menu_label(&self, max_chars: usize, show_sensitive: bool) -> String {
+        let ClipboardHistoryEntry::Text(text) = &self.entry else {
+            return self.preview(max_chars, show_sensitive);
+        };
+        if self.sensitive && !show_sensitive {
+            return self.preview(max_chars, show_sensitive);
+        }
+
+        let mut label = String::new();
+        for (idx, char) in text.chars().enumerate() {
+            if idx == max_chars {
+                label.push_str("...");
+                break;
+            }
+            match char {
+                '\\' => label.push_str("\\\\"),
+                '\n' => label.push_str("\\n"),
+                '\r' => label.push_str("\\r"),
+                '\t' => label.push_str("\\t"),
+                char if char.is_control() => label.push_str(&char.escape_unicode().to_string()),
+                char => label.push(char),
+            }
+        }
+        label
+    }
+
+    /// Case insensitive match of the text, or of "image WxH" for images.
+    pub fn matches(&self, query: &str) -> bool {
+        let query = query.to_lowercase();
+        match &self.entry {
+            ClipboardHistoryEntry::Text(text) => text.to_lowercase().contains(&query),
+            ClipboardHistoryEntry::Image(image) => {
+                format!("image {}x{}", image.width, image.height).contains(&query)
+            }
+        }
+    }
+}
@@ -0,0 +1,55 @@
+//! PNG conversion of clipboard images, which are RGBA pixels.
+
+use crate::history::ClipboardImageEntry;
+
+use anyhow::{anyhow, Context, Result};
+use std::io::{Read, Write};
+
+/// PNG files start with these bytes.
+pub const PNG_SIGNATURE: &[u8] = b"\x89PNG\r\n\x1a\n";
+
+pub fn decode_png(reader: impl Read) -> Result<ClipboardImageEntry> {
+    let mut decoder = png::Decoder::new(reader);
+    decoder.set_transformations(png::Transformations::normalize_to_color8());
+    let mut reader = decoder.read_info().context("Not a valid PNG image.")?;
+
+    let mut pixels = vec![0; reader.output_buffer_size()];
+    let info = reader
+        .next_frame(&mut pixels)
+        .context("Could not decode the PNG image.")?;
+    pixels.truncate(info.buffer_size());
+
+    let bytes = match info.color_type {
+        png::ColorType::Rgba => pixels,
+        png::ColorType::Rgb => pixels
+            .chunks_exact(3)
+            .flat_map(|rgb| [rgb[0], rgb[1], rgb[2], u8::MAX])
+            .collect(),
+        png::ColorType::GrayscaleAlpha => pixels
+            .chunks_exact(2)
+            .flat_map(|ga| [ga[0], ga[0], ga[0], ga[1]])
+            .collect(),
+        png::ColorType::Grayscale => pixels
+            .iter()
+            .flat_map(|&gray| [gray, gray, gray, u8::MAX])
+            .collect(),
+        // Expanded to RGB by `normalize_to_color8`
+        png::ColorType::Indexed => return Err(anyhow!("Unsupported indexed PNG colors.")),
+    };
+
+    Ok(ClipboardImageEntry {
+        width: info.width as usize,
+        height: info.height as usize,
+        bytes,
+    })
+}
+
+pub fn encode_png(image: &ClipboardImageEntry, writer: impl Write) -> Result<()> {
+    let mut encoder = png::Encoder::new(writer, image.width as u32, image.height as u32);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder
+        .write_header()
+        .and_then(|mut writer| writer.write_image_data(&image.bytes))
+        .context("Could not encode the PNG image.")
+}
@@ -0,0 +1,110 @@
+mod args;
+mod base64;
+#[path = "../common/client.rs"]
+mod client;
+mod commands;
+mod compat;
+mod display;
+#[path = "../common/history.rs"]
+mod history;
+mod image;
+#[path = "../common/ipc.rs"]
+mod ipc;
+#[path = "../common/protocol.rs"]
+mod protocol;
+#[path = "../common/util.rs"]
+mod util;
+
+use anyhow::{anyhow, Result};
+use args::Args;
+use std::env;
+use std::path::Path;
+
+const USAGE: &str = "\
+Usage: clippo <command> [arguments]
+
+Entries are selected by their position in `clippo list` (1 is the most recent)
+or by their id, ex: id:42.
+
+Pick an entry from a launcher:
+  clippo pick | rofi -dmenu | clippo pick --select
+  clippo pick | fzf --delimiter '\\t' --with-nth 2.. | clippo pick --select
+
+Replace xclip, wl-copy or wl-paste, their copies are then recorded in the history:
+  ln -s \"$(command -v clippo)\" ~/.local/bin/xclip
+  clippo xclip -selection clipboard -o
+
+Commands:
+  list [--json] [--show-sensitive]           List the history, newest first
+  search <query> [--json] [--show-sensitive] List entries containing <query>
+  get <entry>                                Print the text of an entry
+  copy <entry>                               Put an entry on the clipboard
+  osc52 <entry> [--tmux | --no-tmux]         Copy an entry through the terminal, ex: over SSH
+        [--max-bytes <n>]
+  add [--copy] [--image <file.png>]          Add stdin, or an image, to the history
+  pick [--show-sensitive]                    Print entries for dmenu, rofi or fzf
+  pick --select                              Copy the entry picked in the launcher
+  watch [--json] [--include-images]          Print history changes as they happen
+        [--show-sensitive]
+  delete <entry>                             Remove an entry from the history
+  pin <entry>, unpin <entry>                 Keep an entry from being evicted
+  clear                                      Clear the whole history
+  status [--json]                            Show the daemon status
+  xclip, wl-copy, wl-paste [arguments]       Same as the commands they are named after
+  help                                       Show this message";
+
+fn main() -> Result<()> {
+    // Exit quietly when the output is closed early, ex: `clippo list | head`,
+    // Rust ignores SIGPIPE by default which turns it into print panics
+    #[cfg(unix)]
+    // SAFETY: called before any other thread exists, SIG_DFL is a valid handler.
+    unsafe {
+        libc::signal(libc::SIGPIPE, libc::SIG_DFL);
+    }
+
+    let mut args = env::args();
+    let program = args.next().unwrap_or_default();
+    let mut args: Vec<String> = args.collect();
+
+    // Run through a link named after a compatible command, ex: xclip -> clippo
+    let program_name = Path::new(&program)
+        .file_name()
+        .and_then(|name| name.to_str())
+        .unwrap_or_default();
+    if let Some(compat_command) = compat::command(program_name) {
+        return compat_command(args);
+    }
+    if let Some(compat_command) = args.first().and_then(|name| compat::command(name)) {
+        return compat_command(args.split_off(1));
+    }
+
+    let mut args = Args::new(args);
+    let Some(command) = args.next_positional() else {
+        if args.flag("--help") || args.flag("-h") {
+            println!("{USAGE}");
+            return Ok(());
+        }
+        return Err(anyhow!("Missing command.\n\n{USAGE}"));
+    };
+
+    match command.as_str() {
+        "list" => commands::list(args),
+        "search" => commands::search(args),
+        "get" => commands::get(args),
+        "copy" => commands::copy(args),
+        "osc52" => commands::osc52(args),
+        "add" => commands::add(args),
+        "pick" => commands::pick(args),
+        "watch" => commands::watch(args),
+        "delete" => commands::delete(args),
+        "pin" => commands::pin(args, true),
+        "unpin" => commands::pin(args, false),
+        "clear" => commands::clear(args),
+        "status" => commands::status(args),
+        "help" => {
+            println!("{USAGE}");
+            Ok(())
+        }
+        command => Err(anyhow!("Unknown command \"{command}\".\n\n{USAGE}")),
+    }
+}
@@ -0,0 +1,128 @@
+//! Connection to the daemon, see `protocol.rs` for the messages.
+
+// Included by each client binary, which only uses part of it
+#![allow(dead_code)]
+
+use crate::history::{ClipboardHistoryEntry, HistoryItem};
+use crate::ipc::{self, IpcStream};
+use crate::protocol::{
+    read_frame, write_frame, EntrySelector, Event, Request, RequestFrame, Response, ResponseFrame,
+    PROTOCOL_VERSION,
+};
+
+use anyhow::{anyhow, Context, Result};
+
+pub struct DaemonClient {
+    stream: IpcStream,
+    next_id: u64,
+}
+
+impl DaemonClient {
+    /// Connect to the daemon and perform the version handshake.
+    pub fn connect() -> Result<Self> {
+        let stream = ipc::connect(ipc::DAEMON_SOCKET).context(format!(
+            "Could not reach the daemon on {}, is it running?",
+            ipc::address(ipc::DAEMON_SOCKET)
+        ))?;
+        let mut client = Self { stream, next_id: 1 };
+
+        client
+            .request(Request::Hello {
+                version: PROTOCOL_VERSION,
+            })
+            .context("Handshake with the daemon failed.")?;
+
+        Ok(client)
+    }
+
+    /// Send one request and wait for the matching response.
+    /// Error responses from the daemon are turned into errors.
+    pub fn request(&mut self, request: Request) -> Result<Response> {
+        let id = self.next_id;
+        self.next_id += 1;
+
+        write_frame(&mut self.stream, &RequestFrame { id, request })
+            .context("Failed to write request to the daemon.")?;
+
+        let frame: ResponseFrame = read_frame(&mut self.stream)
+            .context("Failed to read response from the daemon.")?
+            .ok_or_else(|| anyhow!("The daemon closed the connection without answering."))?;
+        // Answer to a request the daemon could not decode
+        if let (0, Err(error)) = (frame.id, &frame.result) {
+            return Err(error.clone().into());
+        }
+        if frame.id != id {
+            return Err(anyhow!(
+                "Expected the response to request {id}, got {}.",
+                frame.id
+            ));
+        }
+
+        Ok(frame.result?)
+    }
+
+    /// Send a request expecting a plain `Ok`.
+    pub fn request_ok(&mut self, request: Request) -> Result<()> {
+        match self.request(request)? {
+            Response::Ok => Ok(()),
+            response => Err(anyhow!("Unexpected response from daemon: {response:?}")),
+        }
+    }
+
+    /// The history, newest entry first. Images come without their pixels.
+    pub fn history(&mut self) -> Result<Vec<HistoryItem>> {
+        match self.request(Request::GetHistory)? {
+            Response::History(items) => Ok(items),
+            response => Err(anyhow!(
+                "Unexpected response to history request: {response:?}"
+            )),
+        }
+    }
+
+    /// A single entry, image pixels included.
+    pub fn entry(&mut self, id: u64) -> Result<HistoryItem> {
+        match self.request(Request::GetEntry { id })? {
+            Response::Entry(item) => Ok(item),
+            response => Err(anyhow!(
+                "Unexpected response to entry request: {response:?}"
+            )),
+        }
+    }
+
+    /// The entry at a position or with an id, without the image pixels.
+    pub fn find_entry(&mut self, entry: EntrySelector) -> Result<HistoryItem> {
+        match self.request(Request::FindEntry { entry })? {
+            Response::Entry(item) => Ok(item),
+            response => Err(anyhow!(
+                "Unexpected response to entry request: {response:?}"
+            )),
+        }
+    }
+
+    /// Current clipboard content, `None` if it is empty.
+    pub fn clipboard(&mut self) -> Result<Option<ClipboardHistoryEntry>> {
+        match self.request(Request::GetClipboard)? {
+            Response::Clipboard(entry) => Ok(entry),
+            response => Err(anyhow!(
+                "Unexpected response to clipboard request: {response:?}"
+            )),
+        }
+    }
+
+    /// Subscribe to history changes, returns the current history.
+    /// Images in the history and the events come without their pixels, see `entry`.
+    /// The connection then only carries events, read with `next_event`.
+    pub fn subscribe(&mut self) -> Result<Vec<HistoryItem>> {
+        match self.request(Request::Subscribe)? {
+            Response::History(items) => Ok(items),
+            response => Err(anyhow!(
+                "Unexpected response to the subscription request: {response:?}"
+            )),
+        }
+    }
+
+    /// Wait for the next history change, `None` once the daemon closed the connection.
+    pub fn next_event(&mut self) -> Result<Option<Event>> {
+        read_frame(&mut self.stream).context("Could not read history event.")
+    }
+}
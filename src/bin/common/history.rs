@@ -0,0 +1,154 @@
+//! History entries as kept by the daemon and sent to its clients.
+
+// Included by each binary, which only uses part of it
+#![allow(dead_code)]
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
+pub enum ClipboardHistoryEntry {
+    Text(String),
+    Image(ClipboardImageEntry),
+}
+
+impl ClipboardHistoryEntry {
+    /// Size of the entry content in bytes, used for the history byte budget.
+    /// Images are measured by their dimensions, which are kept when the pixels are not sent.
+    pub fn size_bytes(&self) -> usize {
+        match self {
+            ClipboardHistoryEntry::Text(text) => text.len(),
+            ClipboardHistoryEntry::Image(image) => image.expected_len(),
+        }
+    }
+}
+
+/// What an entry holds, for the limits and rules that depend on it.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ContentKind {
+    Text,
+    Image,
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
+pub struct ClipboardImageEntry {
+    pub width: usize,
+    pub height: usize,
+    /// RGBA pixels. Empty in history listings and events, see `Request::GetEntry`.
+    pub bytes: Vec<u8>,
+}
+
+impl ClipboardImageEntry {
+    /// Length of the RGBA pixels of an image of this size.
+    pub fn expected_len(&self) -> usize {
+        self.width.saturating_mul(self.height).saturating_mul(4)
+    }
+}
+
+/// Application that owned the clipboard when an entry was captured.
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
+pub struct SourceApp {
+    /// Instance part of `WM_CLASS`, ex: "Navigator".
+    #[serde(default)]
+    pub window_instance: Option<String>,
+    /// Class part of `WM_CLASS`, ex: "firefox".
+    #[serde(default)]
+    pub window_class: Option<String>,
+    /// Name of the owning process, ex: "keepassxc".
+    #[serde(default)]
+    pub process_name: Option<String>,
+}
+
+impl SourceApp {
+    /// Best available name for logs and display.
+    pub fn name(&self) -> &str {
+        self.window_class
+            .as_deref()
+            .or(self.process_name.as_deref())
+            .or(self.window_instance.as_deref())
+            .unwrap_or("unknown")
+    }
+}
+
+/// A history entry along with the metadata the daemon keeps about it.
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
+pub struct HistoryItem {
+    /// Stable identifier used by requests targeting a single entry.
+    #[serde(default)]
+    pub id: u64,
+    pub entry: ClipboardHistoryEntry,
+    /// Capture time, in seconds since the Unix epoch.
+    pub captured_at: u64,
+    /// Pinned entries are exempt from retention rules.
+    #[serde(default)]
+    pub pinned: bool,
+    /// Explicit expiry time, in seconds since the Unix epoch (set by secret rules).
+    #[serde(default)]
+    pub expires_at: Option<u64>,
+    /// Application that owned the clipboard when the entry was captured.
+    #[serde(default)]
+    pub source_app: Option<SourceApp>,
+    /// Matched by a secret rule (or marked by the user), cleared from the clipboard
+    /// according to the sensitive clipboard policy.
+    #[serde(default)]
+    pub sensitive: bool,
+}
+
+impl HistoryItem {
+    /// Copy of the item with an empty image buffer, as sent in history listings and events.
+    pub fn without_pixels(&self) -> Self {
+        let entry = match &self.entry {
+            ClipboardHistoryEntry::Text(text) => ClipboardHistoryEntry::Text(text.clone()),
+            ClipboardHistoryEntry::Image(image) => {
+                ClipboardHistoryEntry::Image(ClipboardImageEntry {
+                    width: image.width,
+                    height: image.height,
+                    bytes: Vec::new(),
+                })
+            }
+        };
+
+        Self {
+            entry,
+            source_app: self.source_app.clone(),
+            ..*self
+        }
+    }
+
+    pub fn kind(&self) -> ContentKind {
+        match self.entry {
+            ClipboardHistoryEntry::Text(_) => ContentKind::Text,
+            ClipboardHistoryEntry::Image(_) => ContentKind::Image,
+        }
+    }
+
+    /// Single line preview of at most `max_chars` characters.
+    /// Sensitive entries are masked unless `show_sensitive`.
+    pub fn preview(&self, max_chars: usize, show_sensitive: bool) -> String {
+        let hidden = self.sensitive && !show_sensitive;
+        match &self.entry {
+            // Fixed length so the mask does not leak the length of the secret
+            ClipboardHistoryEntry::Text(_) if hidden => "•".repeat(12),
+            ClipboardHistoryEntry::Text(text) => {
+                let flat = text.replace('\n', " ").replace('\r', "");
+                if flat.chars().count() > max_chars {
+                    let truncated: String = flat.chars().take(max_chars).collect();
+                    format!("{truncated}...")
+                } else {
+                    flat
+                }
+            }
+            ClipboardHistoryEntry::Image(_) if hidden => "Hidden image".to_string(),
+            ClipboardHistoryEntry::Image(image) => {
+                format!("Image ({}x{})", image.width, image.height)
+            }
+        }
+    }
+}
+
+/// Whether the daemon records clipboard changes, toggled by the `Pause` and `Resume` requests.
+#[derive(Clone, Copy, Serialize, Deserialize, Debug, PartialEq)]
+pub enum CaptureState {
+    Recording,
+    /// Paused until the given time in seconds since the Unix epoch, or until resumed if `None`.
+    Paused(Option<u64>),
+}
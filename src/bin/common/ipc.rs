@@ -0,0 +1,267 @@
+//! Local IPC transport between the daemon and its clients.
+//! On Unix this is a Unix socket under `$XDG_RUNTIME_DIR/clippo`, only reachable
+//! by the current user, and both ends check the uid of their peer.
+//! Other platforms fall back to localhost TCP.
+
+// Included by each binary, which only uses part of it
+#![allow(dead_code)]
+
+pub const DAEMON_SOCKET: &str = "daemon.sock";
+pub const DAEMON_LOCK: &str = "daemon.lock";
+pub const UI_SOCKET: &str = "ui.sock";
+pub const UI_LOCK: &str = "ui.lock";
+
+#[cfg(unix)]
+pub use unix::*;
+
+#[cfg(not(unix))]
+pub use tcp::*;
+
+use anyhow::{Context, Result};
+use std::fs::{self, File, OpenOptions, TryLockError};
+use std::io::Write;
+
+/// Exclusive lock held by the running instance, released when dropped or when
+/// the process exits, even if it crashes.
+pub struct InstanceLock {
+    _file: File,
+}
+
+/// Take the lock file `name`. Returns `None` if another process holds it.
+pub fn lock_instance(name: &str) -> Result<Option<InstanceLock>> {
+    let path = lock_path(name)?;
+    let mut file = OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(&path)
+        .context(format!("Could not open lock file \"{}\".", path.display()))?;
+
+    match file.try_lock() {
+        Ok(()) => {}
+        Err(TryLockError::WouldBlock) => return Ok(None),
+        Err(TryLockError::Error(error)) => {
+            return Err(error).context(format!("Could not lock \"{}\".", path.display()))
+        }
+    }
+
+    // Only informative, for the error shown by other instances
+    file.set_len(0)?;
+    write!(file, "{}", std::process::id())?;
+
+    Ok(Some(InstanceLock { _file: file }))
+}
+
+/// Pid of the process holding the lock file `name`, if it can be read.
+pub fn lock_holder(name: &str) -> Option<u32> {
+    let contents = fs::read_to_string(lock_path(name).ok()?).ok()?;
+    contents.trim().parse().ok()
+}
+
+#[cfg(unix)]
+mod unix {
+    use anyhow::{anyhow, Context, Result};
+    use std::env;
+    use std::fs::{self, Permissions};
+    use std::io;
+    use std::os::unix::fs::{DirBuilderExt, MetadataExt, PermissionsExt};
+    use std::os::unix::io::AsRawFd;
+    use std::os::unix::net::{UnixListener, UnixStream};
+    use std::path::PathBuf;
+
+    pub type IpcListener = UnixListener;
+    pub type IpcStream = UnixStream;
+
+    /// Human readable address of a socket, for logs.
+    pub fn address(name: &str) -> String {
+        runtime_dir().join(name).display().to_string()
+    }
+
+    /// Bind the socket `name`, only accessible by the current user.
+    pub fn bind(name: &str) -> Result<IpcListener> {
+        let path = ensure_runtime_dir()?.join(name);
+
+        // Remove a socket left over by a previous run
+        match fs::remove_file(&path) {
+            Ok(()) => {}
+            Err(error) if error.kind() == io::ErrorKind::NotFound => {}
+            Err(error) => {
+                return Err(anyhow!(
+                    "Could not remove stale socket \"{}\": {error}",
+                    path.display()
+                ))
+            }
+        }
+
+        let listener = UnixListener::bind(&path)
+            .context(format!("Could not bind to \"{}\".", path.display()))?;
+        fs::set_permissions(&path, Permissions::from_mode(0o600)).context(format!(
+            "Could not restrict permissions of \"{}\".",
+            path.display()
+        ))?;
+
+        Ok(listener)
+    }
+
+    /// Remove the socket `name`, when the daemon stops.
+    pub fn remove(name: &str) -> Result<()> {
+        let path = runtime_dir().join(name);
+        match fs::remove_file(&path) {
+            Ok(()) => Ok(()),
+            Err(error) if error.kind() == io::ErrorKind::NotFound => Ok(()),
+            Err(error) => Err(anyhow!(
+                "Could not remove socket \"{}\": {error}",
+                path.display()
+            )),
+        }
+    }
+
+    /// Connect to the socket `name`, making sure it is served by the current user.
+    pub fn connect(name: &str) -> Result<IpcStream> {
+        let path = runtime_dir().join(name);
+        let stream = UnixStream::connect(&path)
+            .context(format!("Could not connect to \"{}\".", path.display()))?;
+        check_peer(&stream)?;
+
+        Ok(stream)
+    }
+
+    /// Refuse connections from other users.
+    pub fn check_peer(stream: &IpcStream) -> Result<()> {
+        let peer_uid = peer_uid(stream).context("Could not read IPC peer credentials.")?;
+        let uid = current_uid();
+        if peer_uid != uid {
+            return Err(anyhow!(
+                "Refusing IPC connection from uid {peer_uid}, expected uid {uid}."
+            ));
+        }
+
+        Ok(())
+    }
+
+    pub(super) fn lock_path(name: &str) -> Result<PathBuf> {
+        Ok(ensure_runtime_dir()?.join(name))
+    }
+
+    fn runtime_dir() -> PathBuf {
+        match env::var_os("XDG_RUNTIME_DIR") {
+            Some(dir) => PathBuf::from(dir).join("clippo"),
+            None => env::temp_dir().join(format!("clippo-{}", current_uid())),
+        }
+    }
+
+    fn ensure_runtime_dir() -> Result<PathBuf> {
+        let dir = runtime_dir();
+        fs::DirBuilder::new()
+            .recursive(true)
+            .mode(0o700)
+            .create(&dir)
+            .context(format!("Could not create \"{}\".", dir.display()))?;
+
+        // The directory may already exist, ex: created by someone else in /tmp
+        let metadata = fs::symlink_metadata(&dir)?;
+        if !metadata.is_dir() || metadata.uid() != current_uid() {
+            return Err(anyhow!(
+                "\"{}\" is not a directory owned by the current user.",
+                dir.display()
+            ));
+        }
+        fs::set_permissions(&dir, Permissions::from_mode(0o700))?;
+
+        Ok(dir)
+    }
+
+    fn current_uid() -> u32 {
+        // SAFETY: getuid can not fail.
+        unsafe { libc::getuid() }
+    }
+
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    fn peer_uid(stream: &IpcStream) -> io::Result<u32> {
+        let mut credentials = libc::ucred {
+            pid: 0,
+            uid: 0,
+            gid: 0,
+        };
+        let mut length = std::mem::size_of::<libc::ucred>() as libc::socklen_t;
+
+        // SAFETY: the buffer and its length match the SO_PEERCRED option.
+        let result = unsafe {
+            libc::getsockopt(
+                stream.as_raw_fd(),
+                libc::SOL_SOCKET,
+                libc::SO_PEERCRED,
+                &mut credentials as *mut libc::ucred as *mut libc::c_void,
+                &mut length,
+            )
+        };
+        if result != 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(credentials.uid)
+    }
+
+    #[cfg(not(any(target_os = "linux", target_os = "android")))]
+    fn peer_uid(stream: &IpcStream) -> io::Result<u32> {
+        let mut uid = 0;
+        let mut gid = 0;
+
+        // SAFETY: uid and gid are valid pointers for the duration of the call.
+        let result = unsafe { libc::getpeereid(stream.as_raw_fd(), &mut uid, &mut gid) };
+        if result != 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(uid)
+    }
+}
+
+#[cfg(not(unix))]
+mod tcp {
+    use anyhow::{Context, Result};
+    use std::env;
+    use std::net::{TcpListener, TcpStream};
+    use std::path::PathBuf;
+
+    use super::DAEMON_SOCKET;
+
+    pub type IpcListener = TcpListener;
+    pub type IpcStream = TcpStream;
+
+    const DAEMON_PORT: u32 = 7879;
+    const UI_PORT: u32 = 7878;
+
+    pub fn address(name: &str) -> String {
+        let port = if name == DAEMON_SOCKET {
+            DAEMON_PORT
+        } else {
+            UI_PORT
+        };
+        format!("127.0.0.1:{port}")
+    }
+
+    pub fn bind(name: &str) -> Result<IpcListener> {
+        let address = address(name);
+        TcpListener::bind(&address).context(format!("Could not bind to \"{address}\"."))
+    }
+
+    /// Nothing to clean up for TCP.
+    pub fn remove(_name: &str) -> Result<()> {
+        Ok(())
+    }
+
+    pub fn connect(name: &str) -> Result<IpcStream> {
+        let address = address(name);
+        TcpStream::connect(&address).context(format!("Could not connect to \"{address}\"."))
+    }
+
+    pub(super) fn lock_path(name: &str) -> Result<PathBuf> {
+        Ok(env::temp_dir().join(format!("clippo-{name}")))
+    }
+
+    /// Peer credentials are not available over TCP.
+    pub fn check_peer(_stream: &IpcStream) -> Result<()> {
+        Ok(())
+    }
+}
@@ -0,0 +1,332 @@
+//! Messages exchanged between the daemon and its clients.
+//! Every message is a frame: a big endian `u32` length followed by that many bytes of RON.
+//! A connection starts with a `Hello` handshake, then carries any number of
+//! requests, each answered by a response with the same id.
+//! After a `Subscribe` request the connection only carries `Event`s from the daemon.
+//! A request the daemon can not decode is answered with a `BadRequest` error for the
+//! request id 0, which clients never use, then the connection is closed.
+
+// Included by each binary, which only uses part of it
+#![allow(dead_code)]
+
+use crate::history::{CaptureState, ClipboardHistoryEntry, HistoryItem};
+
+use anyhow::{anyhow, Context, Result};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::io::{self, Read, Write};
+
+/// Bumped on any incompatible change of the messages below.
+pub const PROTOCOL_VERSION: u32 = 2;
+
+/// Upper bound on a single frame, to not allocate garbage lengths.
+const MAX_FRAME_BYTES: usize = 1 << 30;
+
+/// Largest entry that fits in a frame. RON writes image pixels as a list of numbers,
+/// up to 4 bytes per pixel byte (ex: "255,"), the rest is left for the metadata.
+pub const MAX_ENTRY_BYTES: usize = MAX_FRAME_BYTES / 5;
+
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
+pub enum Request {
+    /// Must be the first request of a connection.
+    Hello {
+        version: u32,
+    },
+    /// The history, newest first. Images come without their pixels, see `GetEntry`.
+    GetHistory,
+    ResetHistory,
+    /// Pause capture for `duration_secs`, or until resumed if `None`.
+    Pause {
+        duration_secs: Option<u64>,
+    },
+    Resume,
+    GetCaptureState,
+    SetSensitive {
+        id: u64,
+        sensitive: bool,
+    },
+    /// Answered with the current history, then history changes are sent as events.
+    /// Like `GetHistory`, images in both come without their pixels.
+    Subscribe,
+    GetStatus,
+    /// A single entry, image pixels included.
+    GetEntry {
+        id: u64,
+    },
+    /// A single entry without the image pixels, by its position or its id.
+    /// Lets clients resolve an entry without listing the whole history.
+    FindEntry {
+        entry: EntrySelector,
+    },
+    /// Put the content of an entry on the system clipboard.
+    SetClipboard {
+        id: u64,
+    },
+    /// Current content of the system clipboard, whether it is in history or not.
+    GetClipboard,
+    /// Record an entry as if it was copied, then put it on the clipboard if `set_clipboard`.
+    /// While capture is paused, the entry is only recorded if `explicit`, ex: `clippo add`
+    /// but not a copy through the xclip shim.
+    AddEntry {
+        entry: ClipboardHistoryEntry,
+        set_clipboard: bool,
+        #[serde(default)]
+        explicit: bool,
+    },
+    DeleteEntry {
+        id: u64,
+    },
+    SetPinned {
+        id: u64,
+        pinned: bool,
+    },
+}
+
+/// How a request designates an entry.
+#[derive(Clone, Copy, Serialize, Deserialize, Debug, PartialEq)]
+pub enum EntrySelector {
+    /// 1 based position in the history, newest first.
+    Position(usize),
+    Id(u64),
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
+pub enum Response {
+    Hello {
+        version: u32,
+    },
+    Ok,
+    History(Vec<HistoryItem>),
+    Entry(HistoryItem),
+    CaptureState(CaptureState),
+    Status(DaemonStatus),
+    /// `None` if the clipboard is empty.
+    Clipboard(Option<ClipboardHistoryEntry>),
+    /// Id of the added entry, `None` if it was not recorded: blank text, too large, matched
+    /// a secret rule, no room left next to the pinned entries, or capture is paused and
+    /// the request was not explicit.
+    Added {
+        id: Option<u64>,
+    },
+}
+
+/// Health of the daemon, answer to `GetStatus`.
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
+pub struct DaemonStatus {
+    pub version: String,
+    pub uptime_secs: u64,
+    /// Clipboard backend used for capture, ex: "X11".
+    pub backend: String,
+    pub text_entries: usize,
+    pub image_entries: usize,
+    /// Size of the content of the whole history.
+    pub total_bytes: usize,
+    /// Time of the last entry recorded from the clipboard, in seconds since the Unix epoch.
+    pub last_capture_at: Option<u64>,
+    pub capture_state: CaptureState,
+    pub history_path: String,
+    /// Most recent errors logged by the daemon, oldest first.
+    pub recent_errors: Vec<LoggedError>,
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
+pub struct LoggedError {
+    /// In seconds since the Unix epoch.
+    pub at: u64,
+    pub message: String,
+}
+
+/// A change of the history, sent to subscribers.
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
+pub enum Event {
+    /// A new entry, inserted at the top of the history.
+    EntryAdded(HistoryItem),
+    EntryRemoved {
+        id: u64,
+    },
+    /// The metadata of an existing entry changed, ex: its sensitivity.
+    Updated(HistoryItem),
+    Cleared,
+    /// The daemon is shutting down, the connection closes after this event.
+    Stopping,
+}
+
+#[derive(Clone, Copy, Serialize, Deserialize, Debug, PartialEq)]
+pub enum ErrorKind {
+    BadRequest,
+    NotFound,
+    UnsupportedVersion,
+    Internal,
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
+pub struct ProtocolError {
+    pub kind: ErrorKind,
+    pub message: String,
+}
+
+impl ProtocolError {
+    pub fn new(kind: ErrorKind, message: impl Into<String>) -> Self {
+        Self {
+            kind,
+            message: message.into(),
+        }
+    }
+
+    pub fn internal(error: anyhow::Error) -> Self {
+        Self::new(ErrorKind::Internal, format!("{error:#}"))
+    }
+}
+
+impl fmt::Display for ProtocolError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}: {}", self.kind, self.message)
+    }
+}
+
+impl std::error::Error for ProtocolError {}
+
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
+pub struct RequestFrame {
+    pub id: u64,
+    pub request: Request,
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
+pub struct ResponseFrame {
+    /// Id of the request this answers.
+    pub id: u64,
+    pub result: Result<Response, ProtocolError>,
+}
+
+pub fn write_frame<T: Serialize>(stream: &mut impl Write, message: &T) -> Result<()> {
+    let payload = ron::ser::to_string(message).context("Could not serialize message.")?;
+    let length = u32::try_from(payload.len())
+        .ok()
+        .filter(|length| *length as usize <= MAX_FRAME_BYTES)
+        .ok_or_else(|| anyhow!("Message of {} bytes is too large.", payload.len()))?;
+
+    stream.write_all(&length.to_be_bytes())?;
+    stream.write_all(payload.as_bytes())?;
+    stream.flush()?;
+    Ok(())
+}
+
+/// Read the next frame. Returns `None` if the peer closed the connection.
+pub fn read_frame<T: DeserializeOwned>(stream: &mut impl Read) -> Result<Option<T>> {
+    read_payload(stream)?
+        .map(|payload| decode_payload(&payload))
+        .transpose()
+}
+
+/// Read the bytes of the next frame, without decoding them.
+/// Returns `None` if the peer closed the connection.
+pub fn read_payload(stream: &mut impl Read) -> Result<Option<Vec<u8>>> {
+    let mut length_bytes = [0; 4];
+    match stream.read_exact(&mut length_bytes) {
+        Ok(()) => {}
+        Err(error) if error.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(error) => return Err(error.into()),
+    }
+
+    let length = u32::from_be_bytes(length_bytes) as usize;
+    if length > MAX_FRAME_BYTES {
+        return Err(anyhow!("Incoming frame of {length} bytes is too large."));
+    }
+
+    let mut payload = vec![0; length];
+    stream
+        .read_exact(&mut payload)
+        .context("Connection closed in the middle of a frame.")?;
+
+    Ok(Some(payload))
+}
+
+/// Decode the bytes of a frame read by `read_payload`.
+pub fn decode_payload<T: DeserializeOwned>(payload: &[u8]) -> Result<T> {
+    ron::de::from_bytes(payload).context("Could not deserialize message.")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn framed(payload: &[u8]) -> Vec<u8> {
+        let mut bytes = (payload.len() as u32).to_be_bytes().to_vec();
+        bytes.extend_from_slice(payload);
+        bytes
+    }
+
+    #[test]
+    fn frames_round_trip() {
+        let request = RequestFrame {
+            id: 7,
+            request: Request::SetPinned {
+                id: 42,
+                pinned: true,
+            },
+        };
+        let response = ResponseFrame {
+            id: 7,
+            result: Err(ProtocolError::new(
+                ErrorKind::NotFound,
+                "No entry with id 42.",
+            )),
+        };
+
+        let mut stream = Vec::new();
+        write_frame(&mut stream, &request).unwrap();
+        write_frame(&mut stream, &response).unwrap();
+
+        let mut stream = Cursor::new(stream);
+        assert_eq!(read_frame(&mut stream).unwrap(), Some(request));
+        assert_eq!(read_frame(&mut stream).unwrap(), Some(response));
+        assert_eq!(read_frame::<ResponseFrame>(&mut stream).unwrap(), None);
+    }
+
+    #[test]
+    fn payload_is_decoded_separately() {
+        let mut stream = Cursor::new(framed(b"RequestFrame(id: 3, request: GetStatus)"));
+
+        let payload = read_payload(&mut stream).unwrap().unwrap();
+        assert_eq!(
+            decode_payload::<RequestFrame>(&payload).unwrap(),
+            RequestFrame {
+                id: 3,
+                request: Request::GetStatus,
+            }
+        );
+    }
+
+    #[test]
+    fn undecodable_payload_is_an_error() {
+        let mut stream = Cursor::new(framed(b"RequestFrame(id: 3, request: Bogus)"));
+
+        let payload = read_payload(&mut stream).unwrap().unwrap();
+        assert!(decode_payload::<RequestFrame>(&payload).is_err());
+    }
+
+    #[test]
+    fn clean_eof_is_none() {
+        let mut stream = Cursor::new(Vec::new());
+        assert_eq!(read_payload(&mut stream).unwrap(), None);
+    }
+
+    #[test]
+    fn eof_in_the_middle_of_a_frame_is_an_error() {
+        let mut bytes = framed(b"RequestFrame(id: 3, request: GetStatus)");
+        bytes.truncate(10);
+
+        assert!(read_payload(&mut Cursor::new(bytes)).is_err());
+    }
+
+    #[test]
+    fn oversized_length_is_rejected() {
+        let length = (MAX_FRAME_BYTES as u32 + 1).to_be_bytes();
+
+        let error = read_payload(&mut Cursor::new(length.to_vec())).unwrap_err();
+        assert!(error.to_string().contains("too large"));
+    }
+}
@@ -1,32 +1,114 @@
-use crate::UI_LISTENING_PORT;
-use crate::UI_SENDING_PORT;
+use crate::config::{
+    AppFilterMode, DaemonConfig, SensitiveClipboardPolicy, SensitiveHistoryPolicy,
+    UnknownTargetsPolicy,
+};
+use crate::error_log::ErrorLog;
+use crate::history::{
+    CaptureState, ClipboardHistoryEntry, ClipboardImageEntry, HistoryItem, SourceApp,
+};
+use crate::ipc::{self, IpcStream};
+use crate::protocol::{
+    decode_payload, read_payload, write_frame, DaemonStatus, EntrySelector, ErrorKind,
+    ProtocolError, Request, RequestFrame, Response, ResponseFrame, PROTOCOL_VERSION,
+};
+use crate::secrets::{SecretFilter, SecretVerdict};
+use crate::selection::{OfferedTargets, SelectionInspector};
+use crate::storage::{self, HistoryStore, Recorded, StorageHandle};
+use crate::util::unix_now;
 
 use anyhow::{anyhow, Context, Result};
 use arboard::{Clipboard, Error as ClipboardError, ImageData};
-use serde::{Deserialize, Serialize};
-use std::fs;
-use std::io::{BufReader, Read, Write};
-use std::net::{Shutdown, TcpListener, TcpStream};
-use std::sync::{Arc, Mutex};
+use std::borrow::Cow;
+use std::env;
+use std::net::Shutdown;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex, PoisonError, RwLock};
+use std::time::Instant;
 use std::{thread, time::Duration};
 
-const HISTORY_FILE_PATH: &str = ".clipboard_history.ron";
-const MAX_HISTORY_LENGTH: usize = 100;
 const CLIPBOARD_REFRESH_RATE_MS: u64 = 800;
 
 const STREAM_MAX_RETRIES: u32 = 5;
+const IPC_RESTART_BASE_DELAY_MS: u64 = 500;
+const IPC_RESTART_MAX_DELAY_MS: u64 = 30_000;
+/// Consecutive listener failures before the daemon gives up.
+const IPC_MAX_RESTARTS: u32 = 5;
+/// A listener that ran this long before failing is considered healthy again.
+const IPC_HEALTHY_AFTER_SECS: u64 = 60;
+/// How long subscribers get to receive the last events when stopping.
+const SHUTDOWN_GRACE_MS: u64 = 1000;
+/// A subscriber that does not read its events for this long is dropped.
+const SUBSCRIBER_WRITE_TIMEOUT_SECS: u64 = 5;
 
-#[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
-pub enum ClipboardHistoryEntry {
-    Text(String),
-    Image(ClipboardImageEntry),
+impl HistoryItem {
+    pub fn new(entry: ClipboardHistoryEntry) -> Self {
+        Self {
+            id: 0,
+            entry,
+            captured_at: unix_now(),
+            pinned: false,
+            expires_at: None,
+            source_app: None,
+            sensitive: false,
+        }
+    }
+}
+
+/// Outcome of `record_entry`.
+struct RecordedEntry {
+    /// Id of the entry in history, `None` if it was not recorded.
+    id: Option<u64>,
+    /// Set if the entry is sensitive: matched by a secret rule or flagged in history.
+    sensitive_capture: Option<SensitiveCapture>,
+}
+
+/// A sensitive copy, returned by `record_entry`.
+struct SensitiveCapture {
+    /// The copy kept in history (possibly redacted), if any.
+    stored_entry: Option<ClipboardHistoryEntry>,
 }
 
-#[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
-pub struct ClipboardImageEntry {
-    pub width: usize,
-    pub height: usize,
-    pub bytes: Vec<u8>,
+/// Whether the owner of a copy marked it as concealed, see `concealment_hints`.
+#[derive(Debug, PartialEq)]
+enum Concealment {
+    NotConcealed,
+    /// The first known hint offered by the owner.
+    Hinted(String),
+    /// The offered targets could not be checked.
+    Unknown,
+}
+
+/// Sensitive content waiting to be cleared from the clipboard.
+struct PendingClear {
+    due: Instant,
+    previous_entry: Option<ClipboardHistoryEntry>,
+    stored_entry: Option<ClipboardHistoryEntry>,
+}
+
+/// Clipboard access from other threads, served by the capture loop which owns the clipboard.
+enum ClipboardAccess {
+    Read {
+        reply: Sender<Result<Option<ClipboardHistoryEntry>>>,
+    },
+    Write {
+        entry: ClipboardHistoryEntry,
+        reply: Sender<Result<()>>,
+    },
+}
+
+/// Clipboard backend arboard uses on this platform, for the status.
+fn clipboard_backend() -> &'static str {
+    if cfg!(target_os = "macos") {
+        "macOS pasteboard"
+    } else if cfg!(windows) {
+        "Windows clipboard"
+    } else if env::var_os("WAYLAND_DISPLAY").is_some() {
+        // arboard talks to XWayland, which mirrors the Wayland clipboard
+        "X11 (XWayland)"
+    } else {
+        "X11"
+    }
 }
 
 impl ClipboardImageEntry {
@@ -37,302 +119,940 @@ impl ClipboardImageEntry {
             bytes: image.bytes.into_owned(),
         }
     }
+
+    fn to_image_data(&self) -> ImageData<'_> {
+        ImageData {
+            width: self.width,
+            height: self.height,
+            bytes: Cow::Borrowed(&self.bytes),
+        }
+    }
 }
 
 pub struct Clippo {
-    clipboard: Mutex<Clipboard>,
-    history: Mutex<Vec<ClipboardHistoryEntry>>,
+    storage: StorageHandle,
+    /// Replaced when the config is reloaded, see `reload_config`.
+    config: RwLock<Arc<DaemonConfig>>,
+    secret_filter: RwLock<Arc<SecretFilter>>,
+    selection_inspector: SelectionInspector,
+    capture_state: Mutex<CaptureState>,
+    /// Set on a stop signal or by a task that failed for good, stops the capture loop
+    /// which returns it.
+    stop_request: Mutex<Option<Result<()>>>,
+    /// Subscriber connections still being written to.
+    active_subscribers: AtomicUsize,
+    started_at: Instant,
+    /// Time of the last entry recorded from the clipboard, in seconds since the Unix epoch.
+    last_capture_at: Mutex<Option<u64>>,
+    error_log: ErrorLog,
+    clipboard_access: Sender<ClipboardAccess>,
+    /// Only used by the capture loop.
+    clipboard_access_requests: Mutex<Receiver<ClipboardAccess>>,
 }
 
 impl Clippo {
-    pub fn new() -> Result<Self> {
-        // Instanciate a clipboard object that will be used to access
-        // or update the system clipboard.
-
+    pub fn new(error_log: ErrorLog) -> Result<Self> {
         // We load the old history when instanciating
         // a new object to ensure history persistance
+        let config = DaemonConfig::load()?;
+        let (clipboard_access, clipboard_access_requests) = mpsc::channel();
+
         Ok(Self {
-            clipboard: Clipboard::new().context("Could not create a clipboard instance, the listener daemon can not run: {clipboard_error}")?.into(),
-            history: Self::load_history()?.into(),
+            storage: HistoryStore::spawn(config.clone())?,
+            secret_filter: RwLock::new(Arc::new(SecretFilter::new(&config.secret_rules))),
+            selection_inspector: SelectionInspector::new(),
+            capture_state: CaptureState::Recording.into(),
+            stop_request: None.into(),
+            active_subscribers: 0.into(),
+            started_at: Instant::now(),
+            last_capture_at: None.into(),
+            error_log,
+            clipboard_access,
+            clipboard_access_requests: clipboard_access_requests.into(),
+            config: RwLock::new(Arc::new(config)),
         })
     }
 
-    /// Monitor clipboard changes and send a request to the UI on copy.
+    fn config(&self) -> Arc<DaemonConfig> {
+        // The config is only ever replaced as a whole, a poisoned lock still holds a valid one
+        Arc::clone(&self.config.read().unwrap_or_else(PoisonError::into_inner))
+    }
+
+    fn secret_filter(&self) -> Arc<SecretFilter> {
+        Arc::clone(
+            &self
+                .secret_filter
+                .read()
+                .unwrap_or_else(PoisonError::into_inner),
+        )
+    }
+
+    /// Read the config file again and apply it to capture and storage.
+    /// The current config is kept if the file can not be read.
+    pub fn reload_config(&self) {
+        let config = match DaemonConfig::load() {
+            Ok(config) => config,
+            Err(e) => {
+                tracing::error!("Could not reload daemon config, keeping the current one: {e:#}");
+                return;
+            }
+        };
+
+        if let Err(e) = self.storage.reload_config(config.clone()) {
+            tracing::error!("Could not apply the reloaded config to the history store: {e}");
+        }
+        *self
+            .secret_filter
+            .write()
+            .unwrap_or_else(PoisonError::into_inner) =
+            Arc::new(SecretFilter::new(&config.secret_rules));
+        *self.config.write().unwrap_or_else(PoisonError::into_inner) = Arc::new(config);
+
+        tracing::info!("Reloaded daemon config ...");
+    }
+
+    /// Monitor clipboard changes and record them in history.
+    /// The clipboard is only accessed from here, the history is owned by the store
+    /// and IPC runs on other threads, so neither can stall capture.
     pub fn monitor_clipboard_events(&self) -> Result<()> {
+        // Instanciate a clipboard object that will be used to access
+        // or update the system clipboard.
+        let mut clipboard = Clipboard::new()
+            .context("Could not create a clipboard instance, the listener daemon can not run.")?;
+        let clipboard_access_requests = self
+            .clipboard_access_requests
+            .lock()
+            .map_err(|e| anyhow!("Could not acquire clipboard access lock: {}", e))?;
+
+        // Last content read from the clipboard, so the same copy is only processed once
+        // even if it was filtered out or altered before being stored.
+        let mut last_clipboard_entry: Option<ClipboardHistoryEntry> = None;
+        // Last clipboard content that was not sensitive, for the `RestorePrevious` policy.
+        let mut last_safe_entry: Option<ClipboardHistoryEntry> = None;
+        let mut pending_clear: Option<PendingClear> = None;
+
         loop {
-            if let Ok(mut clipboard) = self.clipboard.lock() {
-                match Self::read_clipboard_entry(&mut clipboard) {
-                    Ok(Some(entry)) => {
-                        let mut history = self
-                            .history
-                            .lock()
-                            .map_err(|e| anyhow!("Could not acquire history lock: {}", e))?;
-
-                        if !history.contains(&entry) {
-                            // Insert new value at first index
-                            history.insert(0, entry);
-
-                            let history_len = history.len();
-                            // Keep only the wanted number of entries
-                            if history_len > MAX_HISTORY_LENGTH {
-                                history.pop();
-                            }
-
-                            // Explicitly drop the lock otherwise save_history() won't be
-                            // able to access the variable
-                            drop(history);
-
-                            // Send the TCP request to the UI
-                            match TcpStream::connect(format!("127.0.0.1:{UI_SENDING_PORT}")) {
-                                Ok(stream) => match self.send_history(stream) {
-                                    Ok(()) => {
-                                        tracing::info!(
-                                            "Successfully sent history to UI after clipboard event ..."
-                                        );
-                                    }
-                                    Err(e) => {
-                                        tracing::error!(
-                                            "An error occured when sending history to UI after clipboard event: {e} ..."
-                                        );
-                                    }
-                                },
-                                Err(_) => {
-                                    // UI not available
-                                }
-                            }
-
-                            // Save new history to file
-                            match self.save_history() {
-                                Ok(()) => {
-                                    tracing::info!(
-                                        "Successfully saved history after clipboard event ..."
-                                    );
-                                }
-                                Err(e) => {
-                                    tracing::error!(
-                                        "An error occured when saving history to file after clipboard event: {e} ..."
-                                    );
-                                }
-                            }
-                        }
+            if let Some(stop_request) = self.take_stop_request() {
+                // Do not leave sensitive content behind
+                if let Some(pending) = pending_clear.take() {
+                    if let Err(e) = self.clear_sensitive_clipboard(&mut clipboard, pending) {
+                        tracing::error!("Could not clear sensitive clipboard content: {e}");
                     }
-                    Ok(None) => {}
-                    Err(read_clipboard_error) => {
-                        tracing::error!(
-                            "Error getting clipboard content in supported formats: {read_clipboard_error}"
-                        );
+                }
+                // Dropping the clipboard hands its content over to the clipboard manager, if any
+                return stop_request;
+            }
+
+            match Self::read_clipboard_entry(&mut clipboard) {
+                Ok(Some(entry)) if last_clipboard_entry.as_ref() != Some(&entry) => {
+                    last_clipboard_entry = Some(entry.clone());
+
+                    let source_app = self.selection_inspector.owner_app();
+                    let sensitive_capture = if self.is_capture_paused() {
+                        // Still tracked as last entry so it is not recorded on resume
+                        tracing::debug!("Capture is paused, not recording clipboard entry ...");
+                        None
+                    } else {
+                        self.capture_entry(entry.clone(), source_app)?
+                    };
+
+                    // A new copy replaces any sensitive content waiting to be cleared
+                    pending_clear = match sensitive_capture {
+                        Some(capture) => self.schedule_clear(capture, last_safe_entry.clone()),
+                        None => {
+                            last_safe_entry = Some(entry);
+                            None
+                        }
+                    };
+                }
+                Ok(_) => {}
+                Err(read_clipboard_error) => {
+                    tracing::error!(
+                        "Error getting clipboard content in supported formats: {read_clipboard_error}"
+                    );
+                }
+            }
+
+            if pending_clear
+                .as_ref()
+                .is_some_and(|pending| Instant::now() >= pending.due)
+            {
+                if let Some(pending) = pending_clear.take() {
+                    match self.clear_sensitive_clipboard(&mut clipboard, pending) {
+                        Ok(restored_entry) => last_clipboard_entry = restored_entry,
+                        Err(e) => {
+                            tracing::error!("Could not clear sensitive clipboard content: {e}");
+                        }
                     }
                 }
             }
 
-            thread::sleep(Duration::from_millis(CLIPBOARD_REFRESH_RATE_MS));
+            // Wait for the next poll, or access the clipboard on request. Written
+            // content is then picked up by the next poll like any other copy.
+            match clipboard_access_requests
+                .recv_timeout(Duration::from_millis(CLIPBOARD_REFRESH_RATE_MS))
+            {
+                Ok(ClipboardAccess::Read { reply }) => {
+                    let _ = reply.send(Self::read_clipboard_entry(&mut clipboard));
+                }
+                Ok(ClipboardAccess::Write { entry, reply }) => {
+                    let _ = reply.send(Self::write_clipboard(&mut clipboard, &entry));
+                }
+                Err(RecvTimeoutError::Timeout) => {}
+                // The sender lives in self, this can not happen
+                Err(RecvTimeoutError::Disconnected) => {
+                    thread::sleep(Duration::from_millis(CLIPBOARD_REFRESH_RATE_MS));
+                }
+            }
         }
     }
 
+    fn write_clipboard(clipboard: &mut Clipboard, entry: &ClipboardHistoryEntry) -> Result<()> {
+        match entry {
+            ClipboardHistoryEntry::Text(text) => clipboard
+                .set_text(text)
+                .context("Could not set clipboard text value.")?,
+            ClipboardHistoryEntry::Image(image) => clipboard
+                .set_image(image.to_image_data())
+                .context("Could not set clipboard image value.")?,
+        }
+        Ok(())
+    }
+
+    /// Have the capture loop put `entry` on the clipboard, and wait for it.
+    fn set_clipboard(&self, entry: ClipboardHistoryEntry) -> Result<()> {
+        let (reply, result) = mpsc::channel();
+        self.clipboard_access
+            .send(ClipboardAccess::Write { entry, reply })
+            .map_err(|_| anyhow!("The capture loop has stopped."))?;
+        result
+            .recv()
+            .map_err(|_| anyhow!("The capture loop has stopped."))?
+    }
+
+    /// Have the capture loop read the clipboard, which may hold content missing from
+    /// history, ex: copied while capture was paused.
+    fn get_clipboard(&self) -> Result<Option<ClipboardHistoryEntry>> {
+        let (reply, result) = mpsc::channel();
+        self.clipboard_access
+            .send(ClipboardAccess::Read { reply })
+            .map_err(|_| anyhow!("The capture loop has stopped."))?;
+        result
+            .recv()
+            .map_err(|_| anyhow!("The capture loop has stopped."))?
+    }
+
+    fn schedule_clear(
+        &self,
+        capture: SensitiveCapture,
+        previous_entry: Option<ClipboardHistoryEntry>,
+    ) -> Option<PendingClear> {
+        let config = self.config();
+        if config.sensitive_clipboard_policy == SensitiveClipboardPolicy::Keep {
+            return None;
+        }
+
+        tracing::info!(
+            "Sensitive content on the clipboard, clearing it in {} seconds ...",
+            config.sensitive_clear_after_secs
+        );
+        Some(PendingClear {
+            due: Instant::now() + Duration::from_secs(config.sensitive_clear_after_secs),
+            previous_entry,
+            stored_entry: capture.stored_entry,
+        })
+    }
+
+    /// Clear the clipboard, or restore its previous content depending on the policy,
+    /// then apply the history policy to the stored copy.
+    /// Returns the entry put back on the clipboard, if any.
+    fn clear_sensitive_clipboard(
+        &self,
+        clipboard: &mut Clipboard,
+        pending: PendingClear,
+    ) -> Result<Option<ClipboardHistoryEntry>> {
+        let config = self.config();
+        let restored_entry = match (config.sensitive_clipboard_policy, pending.previous_entry) {
+            (SensitiveClipboardPolicy::RestorePrevious, Some(previous_entry)) => {
+                Self::write_clipboard(clipboard, &previous_entry)?;
+                tracing::info!("Restored previous clipboard content after sensitive copy ...");
+                Some(previous_entry)
+            }
+            _ => {
+                clipboard.clear()?;
+                tracing::info!("Cleared sensitive content from the clipboard ...");
+                None
+            }
+        };
+
+        if config.sensitive_history_policy == SensitiveHistoryPolicy::RemoveOnClear {
+            if let Some(stored_entry) = pending.stored_entry {
+                self.storage.remove_entry(stored_entry)?;
+            }
+        }
+
+        Ok(restored_entry)
+    }
+
+    /// Current capture state, resuming capture if a timed pause is over.
+    fn capture_state(&self) -> Result<CaptureState> {
+        let mut capture_state = self
+            .capture_state
+            .lock()
+            .map_err(|e| anyhow!("Could not acquire capture state lock: {}", e))?;
+
+        if let CaptureState::Paused(Some(until)) = *capture_state {
+            if unix_now() >= until {
+                tracing::info!("Pause is over, resuming clipboard capture ...");
+                *capture_state = CaptureState::Recording;
+            }
+        }
+
+        Ok(*capture_state)
+    }
+
+    fn is_capture_paused(&self) -> bool {
+        // Keep recording if the state can not be read
+        matches!(self.capture_state(), Ok(CaptureState::Paused(_)))
+    }
+
+    fn set_capture_state(&self, state: CaptureState) -> Result<()> {
+        let mut capture_state = self
+            .capture_state
+            .lock()
+            .map_err(|e| anyhow!("Could not acquire capture state lock: {}", e))?;
+
+        *capture_state = state;
+        Ok(())
+    }
+
+    /// Apply the concealment hints and the app filter to a new copy, then record it.
+    /// Returns `Some` if the copy is sensitive, see `record_entry`.
+    fn capture_entry(
+        &self,
+        entry: ClipboardHistoryEntry,
+        source_app: Option<SourceApp>,
+    ) -> Result<Option<SensitiveCapture>> {
+        let unverified = match self.concealment() {
+            Concealment::Hinted(hint) => {
+                tracing::info!(
+                    "Skipping clipboard entry marked as concealed by its owner (\"{hint}\") ..."
+                );
+                return Ok(Some(SensitiveCapture { stored_entry: None }));
+            }
+            Concealment::Unknown => match self.config().unknown_targets_policy {
+                UnknownTargetsPolicy::Record => false,
+                UnknownTargetsPolicy::MarkSensitive => {
+                    tracing::info!(
+                        "Could not check clipboard entry for concealment hints, recording it as sensitive ..."
+                    );
+                    true
+                }
+                UnknownTargetsPolicy::Skip => {
+                    tracing::info!(
+                        "Skipping clipboard entry, it could not be checked for concealment hints ..."
+                    );
+                    return Ok(Some(SensitiveCapture { stored_entry: None }));
+                }
+            },
+            Concealment::NotConcealed => false,
+        };
+
+        if !self.is_app_recorded(source_app.as_ref()) {
+            let app_name = source_app.as_ref().map_or("unknown", SourceApp::name);
+            tracing::info!(
+                "Skipping clipboard entry from \"{app_name}\", filtered by the app filter ..."
+            );
+            return Ok(None);
+        }
+
+        Ok(self
+            .record_entry(entry, source_app, unverified)?
+            .sensitive_capture)
+    }
+
+    /// Password managers flag their copies with "do not record" targets,
+    /// looks for a known one offered by the current clipboard owner.
+    fn concealment(&self) -> Concealment {
+        let config = self.config();
+        if config.concealment_hints.is_empty() {
+            return Concealment::NotConcealed;
+        }
+
+        match self.selection_inspector.offered_targets() {
+            OfferedTargets::Known(targets) => targets
+                .into_iter()
+                .find(|target| config.concealment_hints.contains(target))
+                .map_or(Concealment::NotConcealed, Concealment::Hinted),
+            OfferedTargets::Unknown => Concealment::Unknown,
+            // Nothing to check, as if targets were never inspected
+            OfferedTargets::Unsupported => Concealment::NotConcealed,
+        }
+    }
+
+    /// Applies the allow/deny list of the config to the application owning the clipboard.
+    /// With an allowlist, copies from unknown applications are not recorded.
+    fn is_app_recorded(&self, source_app: Option<&SourceApp>) -> bool {
+        let config = self.config();
+        if config.app_filter.is_empty() {
+            return true;
+        }
+
+        let listed =
+            source_app.is_some_and(|app| config.app_filter.iter().any(|name| app.matches(name)));
+
+        match config.app_filter_mode {
+            AppFilterMode::Blocklist => !listed,
+            AppFilterMode::Allowlist => listed,
+        }
+    }
+
+    /// Insert a new entry in the history after running the size limit and the secret rules,
+    /// then hand it to the history store. `sensitive` records it as sensitive even if
+    /// no secret rule matches.
+    fn record_entry(
+        &self,
+        entry: ClipboardHistoryEntry,
+        source_app: Option<SourceApp>,
+        sensitive: bool,
+    ) -> Result<RecordedEntry> {
+        // Still put on the clipboard, ex: an empty line yanked in an editor, but not worth
+        // a history entry
+        if matches!(&entry, ClipboardHistoryEntry::Text(text) if text.trim().is_empty()) {
+            tracing::debug!("Skipping blank clipboard text ...");
+            return Ok(RecordedEntry {
+                id: None,
+                sensitive_capture: None,
+            });
+        }
+
+        let config = self.config();
+        if entry.size_bytes() > config.max_entry_bytes {
+            tracing::debug!(
+                "Skipping clipboard entry of {} bytes, above the configured maximum entry size.",
+                entry.size_bytes()
+            );
+            return Ok(RecordedEntry {
+                id: None,
+                sensitive_capture: None,
+            });
+        }
+
+        let mut expires_at = None;
+        let mut sensitive = sensitive;
+        let entry = match entry {
+            ClipboardHistoryEntry::Text(text) => match self.secret_filter().check(&text) {
+                SecretVerdict::Clean => ClipboardHistoryEntry::Text(text),
+                SecretVerdict::Skip { rule } => {
+                    tracing::info!(
+                        "Skipping clipboard entry matched by secret rule \"{rule}\" ..."
+                    );
+                    return Ok(RecordedEntry {
+                        id: None,
+                        sensitive_capture: Some(SensitiveCapture { stored_entry: None }),
+                    });
+                }
+                SecretVerdict::Store { text, expire } => {
+                    if expire {
+                        expires_at = Some(unix_now() + config.secret_expiry_secs);
+                    }
+                    sensitive = true;
+                    ClipboardHistoryEntry::Text(text)
+                }
+            },
+            image => image,
+        };
+
+        let mut item = HistoryItem::new(entry.clone());
+        item.expires_at = expires_at;
+        item.sensitive = sensitive;
+        item.source_app = source_app;
+
+        let id = match self.storage.record(item)? {
+            Recorded::Inserted { id } => {
+                *self
+                    .last_capture_at
+                    .lock()
+                    .map_err(|e| anyhow!("Could not acquire last capture lock: {}", e))? =
+                    Some(unix_now());
+                id
+            }
+            // Ex: a sensitive entry set back on the clipboard from the UI
+            Recorded::Duplicate {
+                id,
+                sensitive: existing_sensitive,
+            } => {
+                sensitive |= existing_sensitive;
+                id
+            }
+            Recorded::Rejected => {
+                tracing::info!(
+                    "Skipping clipboard entry of {} bytes, pinned entries leave no room for it ...",
+                    entry.size_bytes()
+                );
+                return Ok(RecordedEntry {
+                    id: None,
+                    sensitive_capture: sensitive.then_some(SensitiveCapture { stored_entry: None }),
+                });
+            }
+        };
+
+        Ok(RecordedEntry {
+            id: Some(id),
+            sensitive_capture: sensitive.then_some(SensitiveCapture {
+                stored_entry: Some(entry),
+            }),
+        })
+    }
+
     /// Listen for directives coming from the UI for example clear_history() or the initial
     /// history request when starting. This way the UI can stop and start while always
     /// having an up to date history as long as the clipboard daemon is running.
-    /// We use a simple retry mechanism in case some requests fail.
+    /// The listener runs under a supervisor thread that restarts it with an exponential
+    /// backoff when it fails or panics. If it keeps failing, the daemon is stopped with an
+    /// error so the service manager can restart it.
     pub fn listen_for_ui(self: Arc<Self>) {
-        let clippo = Arc::clone(&self);
-        thread::spawn(move || -> Result<()> {
-            let mut buffer = [0; 512];
+        thread::spawn(move || {
+            let mut restarts = 0;
+            loop {
+                let started_at = Instant::now();
+                let clippo = Arc::clone(&self);
+                let error = match thread::spawn(move || clippo.serve_ipc()).join() {
+                    Ok(Ok(())) => anyhow!("The UI listener stopped accepting connections."),
+                    Ok(Err(error)) => error,
+                    Err(_) => anyhow!("The UI listener panicked."),
+                };
 
-            let listener = TcpListener::bind(format!("127.0.0.1:{UI_LISTENING_PORT}")).context(
-                format!("UI listener could not bind to \"127.0.0.1:{UI_SENDING_PORT}\"."),
-            )?;
+                if started_at.elapsed() >= Duration::from_secs(IPC_HEALTHY_AFTER_SECS) {
+                    restarts = 0;
+                }
+                if restarts >= IPC_MAX_RESTARTS {
+                    tracing::error!(
+                        "UI listener failed {} times in a row, stopping the daemon: {error:#}",
+                        restarts + 1
+                    );
+                    self.fail(error.context("The UI listener could not be restarted."));
+                    return;
+                }
 
-            let mut get_stream_consecutive_failures = 0;
-            for stream in listener.incoming() {
-                let stream_success_result = (|| -> Result<()> {
-                    let mut stream =
-                        stream.context("Could not get stream from incoming UI connexion.")?;
-                    let size = stream
-                        .read(&mut buffer)
-                        .context("Could not read the incoming request from the UI.")?;
-
-                    let request = String::from_utf8_lossy(&buffer[..size]);
-
-                    if request.trim() == "GET_HISTORY" {
-                        clippo
-                            .send_history(stream.try_clone()?)
-                            .context("Could not send the history to UI, stream.write() failed.")?;
-
-                        tracing::info!(
-                            "\"GET_HISTORY\" request received, sending current history to UI ..."
-                        );
-                    } else if request.trim() == "RESET_HISTORY" {
-                        clippo
-                            .clear_history()
-                            .context("Could not clear history after UI request.")?;
-
-                        stream.write(b"OK")?;
-
-                        tracing::info!(
-                            "\"RESET_HISTORY\" request received, clearing current history ..."
-                        );
-                    } else {
-                        stream.write(b"BAD_REQUEST")?;
-                        tracing::warn!(
-                            "Unexpected request received, sending back \"BAD_REQUEST\" to the UI ..."
-                        );
-                    }
-                    Ok(())
-                })();
+                let delay = Duration::from_millis(IPC_RESTART_BASE_DELAY_MS << restarts)
+                    .min(Duration::from_millis(IPC_RESTART_MAX_DELAY_MS));
+                restarts += 1;
+                tracing::warn!(
+                    "UI listener failed: {error:#}. Restarting it in {} ms ({restarts}/{IPC_MAX_RESTARTS}) ...",
+                    delay.as_millis()
+                );
+                thread::sleep(delay);
+            }
+        });
+    }
 
-                match stream_success_result {
-                    Ok(()) => {
-                        // Reset the failure counter on success.
-                        get_stream_consecutive_failures = 0;
-                    }
-                    Err(e) => {
-                        tracing::error!("Error handling UI request: {e}. Retrying...");
-                        get_stream_consecutive_failures += 1;
-                        if get_stream_consecutive_failures >= STREAM_MAX_RETRIES {
-                            tracing::error!("Exceeded {STREAM_MAX_RETRIES} consecutive failures. Exiting UI listener thread.");
-
-                            panic!(
-                                "Exceeded {STREAM_MAX_RETRIES} consecutive failures. Exiting UI listener thread.",
-                            );
+    /// Bind the daemon socket and serve each connection on its own thread,
+    /// see `protocol.rs` for the messages.
+    /// Returns an error after `STREAM_MAX_RETRIES` consecutive accept failures.
+    fn serve_ipc(self: Arc<Self>) -> Result<()> {
+        let listener = ipc::bind(ipc::DAEMON_SOCKET).context("UI listener could not bind.")?;
+
+        let mut get_stream_consecutive_failures = 0;
+        for stream in listener.incoming() {
+            match stream.context("Could not get stream from incoming UI connexion.") {
+                Ok(stream) => {
+                    // Reset the failure counter on success.
+                    get_stream_consecutive_failures = 0;
+
+                    let clippo = Arc::clone(&self);
+                    thread::spawn(move || {
+                        if let Err(e) = clippo.handle_connection(stream) {
+                            tracing::error!("Error handling UI connection: {e:#}");
                         }
-                        thread::sleep(Duration::from_millis(500));
+                    });
+                }
+                Err(e) => {
+                    tracing::error!("Error handling UI request: {e}. Retrying...");
+                    get_stream_consecutive_failures += 1;
+                    if get_stream_consecutive_failures >= STREAM_MAX_RETRIES {
+                        return Err(e.context(format!(
+                            "Exceeded {STREAM_MAX_RETRIES} consecutive failures."
+                        )));
                     }
+                    thread::sleep(Duration::from_millis(500));
                 }
             }
-            Ok(())
-        });
+        }
+        Ok(())
     }
 
-    /// Save clipboard history to ron file.
-    fn save_history(&self) -> Result<()> {
-        let mut file = fs::OpenOptions::new()
-            .create(true)
-            .write(true)
-            .truncate(true)
-            .open(HISTORY_FILE_PATH)
-            .context(format!("Could not create or open {HISTORY_FILE_PATH}"))?;
+    /// Stop the daemon, ex: on SIGTERM. The capture loop returns on its next iteration.
+    pub fn request_stop(&self) {
+        self.set_stop_request(Ok(()));
+    }
 
-        let history = self
-            .history
-            .lock()
-            .map_err(|e| anyhow!("Could not acquire history lock: {}", e))?;
+    /// Record an unrecoverable error, the capture loop returns it on its next iteration.
+    fn fail(&self, error: anyhow::Error) {
+        self.set_stop_request(Err(error));
+    }
 
-        let serialized_history = ron::ser::to_string(&*history)
-            .context("Could not serialize history when saving to file.")?;
+    fn set_stop_request(&self, request: Result<()>) {
+        match self.stop_request.lock() {
+            Ok(mut stop_request) => {
+                // The first reason wins
+                stop_request.get_or_insert(request);
+            }
+            Err(e) => {
+                tracing::error!("Could not acquire stop request lock: {e}, exiting now.");
+                std::process::exit(1);
+            }
+        }
+    }
 
-        file.write_all(serialized_history.as_bytes())
-            .context(format!(
-                "Could not write serialized history to {HISTORY_FILE_PATH}"
-            ))?;
+    fn take_stop_request(&self) -> Option<Result<()>> {
+        self.stop_request.lock().ok()?.take()
+    }
 
-        Ok(())
+    /// Save the history, tell subscribers the daemon is stopping and remove the socket.
+    /// Called once the capture loop returned.
+    pub fn shutdown(&self) {
+        tracing::info!("Stopping Clippo daemon ...");
+
+        if let Err(e) = self.storage.shutdown() {
+            tracing::error!("Could not stop the history store cleanly: {e:#}");
+        }
+
+        // Give subscriber threads a moment to send the last events
+        let deadline = Instant::now() + Duration::from_millis(SHUTDOWN_GRACE_MS);
+        while self.active_subscribers.load(Ordering::Acquire) > 0 && Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(10));
+        }
+
+        if let Err(e) = ipc::remove(ipc::DAEMON_SOCKET) {
+            tracing::error!("Could not remove the daemon socket: {e:#}");
+        }
     }
 
-    /// Loads the current history from the file.
-    /// Static method.
-    fn load_history() -> Result<Vec<ClipboardHistoryEntry>> {
-        let typed_history_result: Result<Vec<ClipboardHistoryEntry>> =
-            fs::File::open(HISTORY_FILE_PATH)
-                .context(format!("Could not open \"{HISTORY_FILE_PATH}\""))
-                .and_then(|file| {
-                    let reader = BufReader::new(file);
-                    ron::de::from_reader(reader).context("Error deserializing clipboard history.")
-                });
+    /// Serve one client: the version handshake, then requests until it disconnects.
+    fn handle_connection(self: &Arc<Self>, mut stream: IpcStream) -> Result<()> {
+        ipc::check_peer(&stream)?;
 
-        match typed_history_result {
-            Ok(history) => Ok(history),
-            Err(typed_load_error) => {
-                let legacy_history_result: Result<Vec<String>> = fs::File::open(HISTORY_FILE_PATH)
-                    .context(format!("Could not open \"{HISTORY_FILE_PATH}\""))
-                    .and_then(|file| {
-                        let reader = BufReader::new(file);
-                        ron::de::from_reader(reader)
-                            .context("Error deserializing legacy clipboard history.")
-                    });
+        let Some(hello) = Self::read_request(&mut stream)? else {
+            return Ok(());
+        };
+        let handshake = match hello.request {
+            Request::Hello { version } if version == PROTOCOL_VERSION => Ok(Response::Hello {
+                version: PROTOCOL_VERSION,
+            }),
+            Request::Hello { version } => Err(ProtocolError::new(
+                ErrorKind::UnsupportedVersion,
+                format!(
+                    "Protocol version {version} is not supported, expected {PROTOCOL_VERSION}."
+                ),
+            )),
+            _ => Err(ProtocolError::new(
+                ErrorKind::BadRequest,
+                "The first request must be Hello.",
+            )),
+        };
+        let accepted = handshake.is_ok();
+        write_frame(
+            &mut stream,
+            &ResponseFrame {
+                id: hello.id,
+                result: handshake,
+            },
+        )?;
+        if !accepted {
+            tracing::warn!("Rejected UI connection with an invalid handshake ...");
+            return Ok(());
+        }
 
-                match legacy_history_result {
-                    Ok(legacy_history) => {
-                        tracing::warn!(
-                            "Loaded legacy string-only clipboard history format in daemon; data will be migrated on next save."
-                        );
-                        Ok(legacy_history
-                            .into_iter()
-                            .map(ClipboardHistoryEntry::Text)
-                            .collect())
-                    }
-                    Err(load_error) => {
-                        eprintln!(
-                            "Could not load typed history: {typed_load_error}\nCould not load legacy history: {load_error}\nFalling back to an empty history.\n",
-                        );
-                        Ok(Vec::new())
-                    }
-                }
+        while let Some(frame) = Self::read_request(&mut stream)? {
+            // The connection is now only used to send events
+            if frame.request == Request::Subscribe {
+                return self.serve_subscriber(stream, frame.id);
             }
+
+            let result = self.handle_request(frame.request);
+            if let Err(error) = &result {
+                tracing::warn!("Request {} failed, sending back \"{error}\" ...", frame.id);
+            }
+            write_frame(
+                &mut stream,
+                &ResponseFrame {
+                    id: frame.id,
+                    result,
+                },
+            )?;
         }
+
+        Ok(())
     }
 
-    fn clear_history(&self) -> Result<()> {
-        let mut history = self
-            .history
-            .lock()
-            .map_err(|e| anyhow!("Could not acquire history lock: {}", e))?;
+    /// Read the next request of a client. A frame that is not a valid request is answered
+    /// with a `BadRequest` error, then `None` is returned to close the connection.
+    fn read_request(stream: &mut IpcStream) -> Result<Option<RequestFrame>> {
+        let Some(payload) = read_payload(stream)? else {
+            return Ok(None);
+        };
 
-        history.clear(); // Clear history in memory
-        match fs::remove_file(HISTORY_FILE_PATH) {
-            Ok(()) => {}
-            Err(error) if error.kind() == std::io::ErrorKind::NotFound => {}
-            Err(error) => {
-                return Err(anyhow!("Could not delete the history file: {error}"));
+        match decode_payload(&payload) {
+            Ok(frame) => Ok(Some(frame)),
+            Err(e) => {
+                tracing::warn!("Closing UI connection after an invalid request: {e:#} ...");
+                write_frame(
+                    stream,
+                    &ResponseFrame {
+                        // The id of the request is unknown, clients never use 0
+                        id: 0,
+                        result: Err(ProtocolError::new(ErrorKind::BadRequest, format!("{e:#}"))),
+                    },
+                )?;
+                Ok(None)
             }
         }
+    }
 
-        // We could also clear the current state of the keyboard
-        // self.clipboard.clear()?;
+    /// Answer the `Subscribe` request `id` with the current history, then forward history
+    /// events to the client from a dedicated writer thread, so a slow client only delays
+    /// its own events. Returns once the client disconnects.
+    fn serve_subscriber(self: &Arc<Self>, mut stream: IpcStream, id: u64) -> Result<()> {
+        let (events, received_events) = mpsc::channel();
+        let (subscriber_id, history) = self.storage.subscribe(events)?;
+
+        let mut writer = stream.try_clone()?;
+        writer.set_write_timeout(Some(Duration::from_secs(SUBSCRIBER_WRITE_TIMEOUT_SECS)))?;
+        write_frame(
+            &mut writer,
+            &ResponseFrame {
+                id,
+                result: Ok(Response::History(history)),
+            },
+        )?;
+
+        self.active_subscribers.fetch_add(1, Ordering::AcqRel);
+        let clippo = Arc::clone(self);
+        thread::spawn(move || {
+            // Ends when the store drops the subscriber, ex: when the daemon stops
+            for event in received_events {
+                if let Err(e) = write_frame(&mut writer, &event) {
+                    tracing::info!("Could not send event to subscriber {subscriber_id}: {e} ...");
+                    break;
+                }
+            }
+            // Also ends the read loop below
+            let _ = writer.shutdown(Shutdown::Both);
+            clippo.active_subscribers.fetch_sub(1, Ordering::AcqRel);
+        });
+
+        // Subscribers send nothing else, wait for them to disconnect so they
+        // are dropped even if no event is published in the meantime
+        while let Ok(Some(_)) = read_payload(&mut stream) {}
+
+        // Fails if the store already stopped, along with the daemon
+        let _ = self.storage.unsubscribe(subscriber_id);
         Ok(())
     }
 
-    fn send_history(&self, mut stream: TcpStream) -> Result<()> {
-        let history = self
-            .history
-            .lock()
-            .map_err(|e| anyhow!("Could not acquire history lock: {}", e))?;
-
-        let serialized_history = ron::ser::to_string(&*history)
-            .context("Could not serialize history when sending to UI.")?;
-
-        for attempt in 0..STREAM_MAX_RETRIES {
-            let send_result = (|| -> Result<()> {
-                stream.write_all(serialized_history.as_bytes())?;
-                stream
-                    .shutdown(Shutdown::Write)
-                    .context("Could not close the TCP connection when sending history.")?;
-                Ok(())
-            })();
-
-            match send_result {
-                Ok(()) => return Ok(()),
-                Err(e) => {
-                    eprintln!(
-                        "Could not send history to UI on attempt {}/{}: {}. Retrying...",
-                        attempt + 1,
-                        STREAM_MAX_RETRIES,
-                        e
-                    );
-                    std::thread::sleep(std::time::Duration::from_millis(500));
+    fn handle_request(&self, request: Request) -> Result<Response, ProtocolError> {
+        match request {
+            Request::Hello { .. } => Err(ProtocolError::new(
+                ErrorKind::BadRequest,
+                "The handshake was already done.",
+            )),
+            // Handled by handle_connection, which hands the stream over
+            Request::Subscribe => Err(ProtocolError::new(
+                ErrorKind::Internal,
+                "Subscribe can not be handled as a regular request.",
+            )),
+            Request::GetHistory => {
+                tracing::info!(
+                    "\"GetHistory\" request received, sending current history to UI ..."
+                );
+
+                self.storage
+                    .history()
+                    .map(Response::History)
+                    .map_err(ProtocolError::internal)
+            }
+            Request::ResetHistory => {
+                tracing::info!("\"ResetHistory\" request received, clearing current history ...");
+
+                self.storage
+                    .clear()
+                    .context("Could not clear history after UI request.")
+                    .map_err(ProtocolError::internal)?;
+                Ok(Response::Ok)
+            }
+            Request::Pause { duration_secs } => {
+                tracing::info!("\"Pause\" request received, pausing clipboard capture ...");
+
+                // Without a duration, capture is paused until resumed
+                let until = duration_secs.map(|secs| unix_now().saturating_add(secs));
+                self.set_capture_state(CaptureState::Paused(until))
+                    .map_err(ProtocolError::internal)?;
+                Ok(Response::Ok)
+            }
+            Request::Resume => {
+                tracing::info!("\"Resume\" request received, resuming clipboard capture ...");
+
+                self.set_capture_state(CaptureState::Recording)
+                    .map_err(ProtocolError::internal)?;
+                Ok(Response::Ok)
+            }
+            Request::GetCaptureState => self
+                .capture_state()
+                .map(Response::CaptureState)
+                .map_err(ProtocolError::internal),
+            Request::SetSensitive { id, sensitive } => {
+                if !self
+                    .storage
+                    .set_sensitive(id, sensitive)
+                    .map_err(ProtocolError::internal)?
+                {
+                    return Err(ProtocolError::new(
+                        ErrorKind::NotFound,
+                        format!("No entry with id {id}."),
+                    ));
                 }
+
+                tracing::info!(
+                    "\"SetSensitive\" request received, entry {id} sensitive: {sensitive} ..."
+                );
+                Ok(Response::Ok)
+            }
+            Request::GetStatus => self
+                .status()
+                .map(Response::Status)
+                .map_err(ProtocolError::internal),
+            Request::GetEntry { id } => match self.storage.entry(id) {
+                Ok(Some(item)) => Ok(Response::Entry(item)),
+                Ok(None) => Err(ProtocolError::new(
+                    ErrorKind::NotFound,
+                    format!("No entry with id {id}."),
+                )),
+                Err(e) => Err(ProtocolError::internal(e)),
+            },
+            Request::FindEntry { entry } => match self.storage.find_entry(entry) {
+                Ok(Some(item)) => Ok(Response::Entry(item)),
+                Ok(None) => Err(ProtocolError::new(
+                    ErrorKind::NotFound,
+                    match entry {
+                        EntrySelector::Position(position) => {
+                            format!("No entry at position {position}.")
+                        }
+                        EntrySelector::Id(id) => format!("No entry with id {id}."),
+                    },
+                )),
+                Err(e) => Err(ProtocolError::internal(e)),
+            },
+            Request::SetClipboard { id } => {
+                let Some(item) = self.storage.entry(id).map_err(ProtocolError::internal)? else {
+                    return Err(ProtocolError::new(
+                        ErrorKind::NotFound,
+                        format!("No entry with id {id}."),
+                    ));
+                };
+
+                tracing::info!("\"SetClipboard\" request received, copying entry {id} ...");
+                self.set_clipboard(item.entry)
+                    .map_err(ProtocolError::internal)?;
+                Ok(Response::Ok)
+            }
+            Request::GetClipboard => self
+                .get_clipboard()
+                .map(Response::Clipboard)
+                .map_err(ProtocolError::internal),
+            Request::AddEntry {
+                entry,
+                set_clipboard,
+                explicit,
+            } => {
+                tracing::info!("\"AddEntry\" request received, recording entry ...");
+
+                // Image sizes are computed from their dimensions, see `size_bytes`
+                if let ClipboardHistoryEntry::Image(image) = &entry {
+                    if image.bytes.len() != image.expected_len() {
+                        return Err(ProtocolError::new(
+                            ErrorKind::BadRequest,
+                            format!(
+                                "Image of {}x{} has {} bytes, expected {} RGBA bytes.",
+                                image.width,
+                                image.height,
+                                image.bytes.len(),
+                                image.expected_len()
+                            ),
+                        ));
+                    }
+                }
+
+                // Ex: a password manager copying through the xclip shim while capture is paused
+                let id = if !explicit && self.is_capture_paused() {
+                    tracing::info!("Capture is paused, the entry is not recorded ...");
+                    None
+                } else {
+                    self.record_entry(entry.clone(), None, false)
+                        .map_err(ProtocolError::internal)?
+                        .id
+                };
+                if set_clipboard {
+                    // Picked up by the capture loop like any copy, ex: to clear it if sensitive
+                    self.set_clipboard(entry).map_err(ProtocolError::internal)?;
+                }
+                Ok(Response::Added { id })
+            }
+            Request::DeleteEntry { id } => {
+                if !self.storage.delete(id).map_err(ProtocolError::internal)? {
+                    return Err(ProtocolError::new(
+                        ErrorKind::NotFound,
+                        format!("No entry with id {id}."),
+                    ));
+                }
+
+                tracing::info!("\"DeleteEntry\" request received, deleted entry {id} ...");
+                Ok(Response::Ok)
+            }
+            Request::SetPinned { id, pinned } => {
+                if !self
+                    .storage
+                    .set_pinned(id, pinned)
+                    .map_err(ProtocolError::internal)?
+                {
+                    return Err(ProtocolError::new(
+                        ErrorKind::NotFound,
+                        format!("No entry with id {id}."),
+                    ));
+                }
+
+                tracing::info!("\"SetPinned\" request received, entry {id} pinned: {pinned} ...");
+                Ok(Response::Ok)
             }
         }
+    }
 
-        Err(anyhow::anyhow!(
-            "Could not send history to UI {} times in a row",
-            STREAM_MAX_RETRIES
-        ))
+    fn status(&self) -> Result<DaemonStatus> {
+        let stats = self.storage.stats()?;
+        let last_capture_at = *self
+            .last_capture_at
+            .lock()
+            .map_err(|e| anyhow!("Could not acquire last capture lock: {}", e))?;
+
+        Ok(DaemonStatus {
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            uptime_secs: self.started_at.elapsed().as_secs(),
+            backend: clipboard_backend().to_string(),
+            text_entries: stats.text_entries,
+            image_entries: stats.image_entries,
+            total_bytes: stats.total_bytes,
+            last_capture_at,
+            capture_state: self.capture_state()?,
+            history_path: storage::history_path().display().to_string(),
+            recent_errors: self.error_log.recent(),
+        })
     }
 
     fn read_clipboard_entry(clipboard: &mut Clipboard) -> Result<Option<ClipboardHistoryEntry>> {
         match clipboard.get_text() {
+            // Blank text is returned for pastes, `record_entry` does not record it
             Ok(content) => {
-                if !content.trim().is_empty() {
+                if !content.is_empty() {
                     return Ok(Some(ClipboardHistoryEntry::Text(content)));
                 }
             }
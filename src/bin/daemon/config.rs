@@ -0,0 +1,298 @@
+use crate::history::ContentKind;
+use crate::protocol::MAX_ENTRY_BYTES;
+
+use anyhow::{anyhow, Context, Result};
+use serde::{Deserialize, Serialize};
+
+const DEFAULT_SWEEP_INTERVAL_SECS: u64 = 60;
+const DEFAULT_MAX_TEXT_ENTRIES: usize = 100;
+const DEFAULT_MAX_IMAGE_ENTRIES: usize = 20;
+const DEFAULT_MAX_TOTAL_BYTES: usize = 256 * 1024 * 1024;
+const DEFAULT_MAX_ENTRY_BYTES: usize = 32 * 1024 * 1024;
+const DEFAULT_SECRET_EXPIRY_SECS: u64 = 300;
+const DEFAULT_SENSITIVE_CLEAR_AFTER_SECS: u64 = 30;
+
+fn default_sweep_interval_secs() -> u64 {
+    DEFAULT_SWEEP_INTERVAL_SECS
+}
+
+fn default_max_text_entries() -> usize {
+    DEFAULT_MAX_TEXT_ENTRIES
+}
+
+fn default_max_image_entries() -> usize {
+    DEFAULT_MAX_IMAGE_ENTRIES
+}
+
+fn default_max_total_bytes() -> usize {
+    DEFAULT_MAX_TOTAL_BYTES
+}
+
+fn default_max_entry_bytes() -> usize {
+    DEFAULT_MAX_ENTRY_BYTES
+}
+
+fn default_secret_expiry_secs() -> u64 {
+    DEFAULT_SECRET_EXPIRY_SECS
+}
+
+fn default_sensitive_clear_after_secs() -> u64 {
+    DEFAULT_SENSITIVE_CLEAR_AFTER_SECS
+}
+
+fn default_concealment_hints() -> Vec<String> {
+    [
+        // KeePassXC, KDE apps
+        "x-kde-passwordManagerHint",
+        // nspasteboard.org convention, used by password managers on macOS and some Linux apps
+        "org.nspasteboard.ConcealedType",
+        "application/x-nspasteboard-concealed-type",
+        // Windows clipboard history exclusion format
+        "ExcludeClipboardContentFromMonitorProcessing",
+    ]
+    .into_iter()
+    .map(String::from)
+    .collect()
+}
+
+fn default_secret_rules() -> Vec<SecretRule> {
+    let builtin = |name: &str, detector, action| SecretRule {
+        name: name.to_string(),
+        detector: Some(detector),
+        pattern: None,
+        action,
+    };
+
+    vec![
+        builtin(
+            "Private key",
+            BuiltinDetector::PrivateKey,
+            SecretAction::Skip,
+        ),
+        builtin(
+            "AWS access key",
+            BuiltinDetector::AwsAccessKey,
+            SecretAction::Skip,
+        ),
+        builtin(
+            "JSON web token",
+            BuiltinDetector::JsonWebToken,
+            SecretAction::Skip,
+        ),
+        builtin("API token", BuiltinDetector::ApiToken, SecretAction::Skip),
+        builtin(
+            "Credit card number",
+            BuiltinDetector::CreditCard,
+            SecretAction::Redact,
+        ),
+        builtin(
+            "One-time code",
+            BuiltinDetector::OneTimeCode,
+            SecretAction::Expire,
+        ),
+    ]
+}
+
+/// Which kind of entry a retention rule applies to.
+#[derive(Clone, Copy, Serialize, Deserialize, Debug, PartialEq)]
+pub enum EntryKind {
+    Any,
+    Text,
+    Image,
+}
+
+impl EntryKind {
+    /// Whether a rule for this kind applies to entries holding `content`.
+    pub fn applies_to(self, content: ContentKind) -> bool {
+        match self {
+            EntryKind::Any => true,
+            EntryKind::Text => content == ContentKind::Text,
+            EntryKind::Image => content == ContentKind::Image,
+        }
+    }
+}
+
+/// Drop entries of the given kind once they are older than `max_age_secs`.
+/// Pinned entries are never dropped by retention rules.
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct RetentionRule {
+    pub kind: EntryKind,
+    pub max_age_secs: u64,
+}
+
+/// Detectors shipped with the daemon, see `secrets.rs`.
+#[derive(Clone, Copy, Serialize, Deserialize, Debug, PartialEq)]
+pub enum BuiltinDetector {
+    PrivateKey,
+    AwsAccessKey,
+    JsonWebToken,
+    ApiToken,
+    CreditCard,
+    OneTimeCode,
+}
+
+/// What to do with a text entry matched by a secret rule.
+#[derive(Clone, Copy, Serialize, Deserialize, Debug, PartialEq)]
+pub enum SecretAction {
+    /// Do not record the entry.
+    Skip,
+    /// Record the entry with the matched parts replaced.
+    Redact,
+    /// Record the entry but drop it after `secret_expiry_secs`.
+    Expire,
+}
+
+/// A secret rule matches either a built-in detector or a user regex `pattern`.
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct SecretRule {
+    pub name: String,
+    #[serde(default)]
+    pub detector: Option<BuiltinDetector>,
+    #[serde(default)]
+    pub pattern: Option<String>,
+    pub action: SecretAction,
+}
+
+/// How `app_filter` decides which applications are recorded.
+#[derive(Clone, Copy, Serialize, Deserialize, Debug, PartialEq, Default)]
+pub enum AppFilterMode {
+    /// Record everything except copies from the listed applications.
+    #[default]
+    Blocklist,
+    /// Only record copies from the listed applications.
+    Allowlist,
+}
+
+/// What happens to a copy whose targets could not be checked for `concealment_hints`,
+/// ex: the owner did not answer in time. It may come from a password manager.
+#[derive(Clone, Copy, Serialize, Deserialize, Debug, PartialEq, Default)]
+pub enum UnknownTargetsPolicy {
+    /// Record it like any other copy.
+    Record,
+    /// Record it as sensitive: masked in the UI and handled by the sensitive policies.
+    #[default]
+    MarkSensitive,
+    /// Do not record it.
+    Skip,
+}
+
+/// What happens to the system clipboard some time after sensitive content was copied
+/// (matched by a secret rule, flagged by a password manager or marked by the user).
+#[derive(Clone, Copy, Serialize, Deserialize, Debug, PartialEq, Default)]
+pub enum SensitiveClipboardPolicy {
+    /// Leave the clipboard alone.
+    #[default]
+    Keep,
+    /// Empty the clipboard.
+    Clear,
+    /// Put back the last non sensitive clipboard content, or empty it if there is none.
+    RestorePrevious,
+}
+
+/// What happens to the history copy of a sensitive entry when the clipboard is cleared.
+#[derive(Clone, Copy, Serialize, Deserialize, Debug, PartialEq, Default)]
+pub enum SensitiveHistoryPolicy {
+    /// Keep it, subject to the secret rules expiry.
+    #[default]
+    Keep,
+    /// Remove it from history along with the clipboard content.
+    RemoveOnClear,
+}
+
+/// Daemon configuration, stored next to the UI config as `daemon.toml`.
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct DaemonConfig {
+    #[serde(default = "default_sweep_interval_secs")]
+    pub sweep_interval_secs: u64,
+    #[serde(default = "default_max_text_entries")]
+    pub max_text_entries: usize,
+    #[serde(default = "default_max_image_entries")]
+    pub max_image_entries: usize,
+    /// Budget for the content of the whole history, in bytes.
+    #[serde(default = "default_max_total_bytes")]
+    pub max_total_bytes: usize,
+    /// Entries bigger than this are not recorded at all.
+    #[serde(default = "default_max_entry_bytes")]
+    pub max_entry_bytes: usize,
+    // Not written when empty: an inline `retention_rules = []` would clash with the
+    // `[[retention_rules]]` tables users add below it
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub retention_rules: Vec<RetentionRule>,
+    #[serde(default = "default_secret_expiry_secs")]
+    pub secret_expiry_secs: u64,
+    #[serde(default = "default_secret_rules")]
+    pub secret_rules: Vec<SecretRule>,
+    /// Copies offering one of these targets are never recorded.
+    #[serde(default = "default_concealment_hints")]
+    pub concealment_hints: Vec<String>,
+    #[serde(default)]
+    pub unknown_targets_policy: UnknownTargetsPolicy,
+    #[serde(default)]
+    pub app_filter_mode: AppFilterMode,
+    /// Window classes or process names, matched without case.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub app_filter: Vec<String>,
+    #[serde(default)]
+    pub sensitive_clipboard_policy: SensitiveClipboardPolicy,
+    #[serde(default = "default_sensitive_clear_after_secs")]
+    pub sensitive_clear_after_secs: u64,
+    #[serde(default)]
+    pub sensitive_history_policy: SensitiveHistoryPolicy,
+}
+
+impl Default for DaemonConfig {
+    fn default() -> Self {
+        Self {
+            sweep_interval_secs: DEFAULT_SWEEP_INTERVAL_SECS,
+            max_text_entries: DEFAULT_MAX_TEXT_ENTRIES,
+            max_image_entries: DEFAULT_MAX_IMAGE_ENTRIES,
+            max_total_bytes: DEFAULT_MAX_TOTAL_BYTES,
+            max_entry_bytes: DEFAULT_MAX_ENTRY_BYTES,
+            retention_rules: Vec::new(),
+            secret_expiry_secs: DEFAULT_SECRET_EXPIRY_SECS,
+            secret_rules: default_secret_rules(),
+            concealment_hints: default_concealment_hints(),
+            unknown_targets_policy: UnknownTargetsPolicy::MarkSensitive,
+            app_filter_mode: AppFilterMode::Blocklist,
+            app_filter: Vec::new(),
+            sensitive_clipboard_policy: SensitiveClipboardPolicy::Keep,
+            sensitive_clear_after_secs: DEFAULT_SENSITIVE_CLEAR_AFTER_SECS,
+            sensitive_history_policy: SensitiveHistoryPolicy::Keep,
+        }
+    }
+}
+
+impl DaemonConfig {
+    /// Loads the daemon config, creating it with defaults if it does not exist.
+    /// A file that can not be parsed is an error rather than a fallback to defaults,
+    /// which would silently turn off the retention and secret rules it sets.
+    pub fn load() -> Result<Self> {
+        let path = confy::get_configuration_file_path("clippo", "daemon")
+            .context("Could not locate the daemon config.")?;
+        let config: Self = confy::load("clippo", "daemon").context(format!(
+            "Could not load the daemon config from {}.",
+            path.display()
+        ))?;
+
+        // An entry bigger than the whole budget would be recorded, then evicted right away
+        if config.max_entry_bytes > config.max_total_bytes {
+            return Err(anyhow!(
+                "Invalid daemon config {}: max_entry_bytes ({}) is above max_total_bytes ({}).",
+                path.display(),
+                config.max_entry_bytes,
+                config.max_total_bytes
+            ));
+        }
+
+        if config.max_entry_bytes > MAX_ENTRY_BYTES {
+            return Err(anyhow!(
+                "Invalid daemon config {}: max_entry_bytes ({}) is above {}, the largest entry sent to clients.",
+                path.display(),
+                config.max_entry_bytes,
+                MAX_ENTRY_BYTES
+            ));
+        }
+
+        Ok(config)
+    }
+}
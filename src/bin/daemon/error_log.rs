@@ -0,0 +1,67 @@
+//! Keeps the last errors logged by the daemon, so the `GetStatus` request can report them.
+//! Installed as a `tracing` layer next to the regular log output.
+
+use crate::protocol::LoggedError;
+use crate::util::unix_now;
+
+use std::collections::VecDeque;
+use std::fmt;
+use std::sync::{Arc, Mutex, PoisonError};
+use tracing::field::{Field, Visit};
+use tracing::{Event, Level, Subscriber};
+use tracing_subscriber::layer::{Context, Layer};
+
+const MAX_LOGGED_ERRORS: usize = 20;
+
+/// Shared log of the most recent errors. Cheap to clone.
+#[derive(Clone, Default)]
+pub struct ErrorLog {
+    errors: Arc<Mutex<VecDeque<LoggedError>>>,
+}
+
+impl ErrorLog {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The most recent errors, oldest first.
+    pub fn recent(&self) -> Vec<LoggedError> {
+        // Never panic here, the log is also written from the logging layer
+        let errors = self.errors.lock().unwrap_or_else(PoisonError::into_inner);
+        errors.iter().cloned().collect()
+    }
+
+    fn push(&self, message: String) {
+        let mut errors = self.errors.lock().unwrap_or_else(PoisonError::into_inner);
+        if errors.len() == MAX_LOGGED_ERRORS {
+            errors.pop_front();
+        }
+        errors.push_back(LoggedError {
+            at: unix_now(),
+            message,
+        });
+    }
+}
+
+impl<S: Subscriber> Layer<S> for ErrorLog {
+    fn on_event(&self, event: &Event<'_>, _ctx: Context<'_, S>) {
+        if *event.metadata().level() != Level::ERROR {
+            return;
+        }
+
+        let mut visitor = MessageVisitor(String::new());
+        event.record(&mut visitor);
+        self.push(visitor.0);
+    }
+}
+
+/// Extracts the formatted message of an event.
+struct MessageVisitor(String);
+
+impl Visit for MessageVisitor {
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        if field.name() == "message" {
+            self.0 = format!("{value:?}");
+        }
+    }
+}
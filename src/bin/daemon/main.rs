@@ -1,26 +1,70 @@
 mod clipboard_daemon;
+mod config;
+mod error_log;
+#[path = "../common/history.rs"]
+mod history;
+#[path = "../common/ipc.rs"]
+mod ipc;
+#[path = "../common/protocol.rs"]
+mod protocol;
+mod secrets;
+mod selection;
+mod signals;
+mod storage;
+#[path = "../common/util.rs"]
+mod util;
 
 use std::sync::Arc;
 
-use anyhow::Result;
+use anyhow::{anyhow, Result};
 use clipboard_daemon::Clippo;
-
-const UI_SENDING_PORT: u32 = 7878;
-const UI_LISTENING_PORT: u32 = 7879;
+use error_log::ErrorLog;
+use signals::Signal;
+use tracing_subscriber::filter::LevelFilter;
+use tracing_subscriber::prelude::*;
 
 fn main() -> Result<()> {
-    // Init logging
-    tracing_subscriber::fmt::init();
+    // Init logging, errors are also kept for the status request
+    let error_log = ErrorLog::new();
+    tracing_subscriber::registry()
+        .with(LevelFilter::INFO)
+        .with(tracing_subscriber::fmt::layer())
+        .with(error_log.clone())
+        .init();
+
+    // Before any thread is spawned, so signals are only received by the signal thread
+    signals::block()?;
+
+    // Held until the daemon exits, two daemons would race on the clipboard and the history file
+    let Some(_instance_lock) = ipc::lock_instance(ipc::DAEMON_LOCK)? else {
+        return Err(match ipc::lock_holder(ipc::DAEMON_LOCK) {
+            Some(pid) => anyhow!("Another Clippo daemon is already running (pid {pid}), exiting."),
+            None => anyhow!("Another Clippo daemon is already running, exiting."),
+        });
+    };
+
+    let clippo = Arc::new(Clippo::new(error_log)?);
 
-    let clippo = Arc::new(Clippo::new()?);
+    let signal_clippo = Arc::clone(&clippo);
+    signals::listen(move |signal| match signal {
+        Signal::Stop => {
+            tracing::info!("Stop signal received ...");
+            signal_clippo.request_stop();
+        }
+        Signal::Reload => signal_clippo.reload_config(),
+    });
 
     // Spawn the UI listener thread. This works because listen_for_ui expects an Arc<Self>.
-    tracing::info!("Clippo listening for UI requests on 127.0.0.1:{UI_LISTENING_PORT} ...");
+    tracing::info!(
+        "Clippo listening for UI requests on {} ...",
+        ipc::address(ipc::DAEMON_SOCKET)
+    );
     Arc::clone(&clippo).listen_for_ui();
 
     // Main thread
-    tracing::info!("Clippo listening for clipboard changes and ready to send to UI on 127.0.0.1:{UI_SENDING_PORT} ...");
-    clippo.monitor_clipboard_events()?;
+    tracing::info!("Clippo listening for clipboard changes ...");
+    let result = clippo.monitor_clipboard_events();
+    clippo.shutdown();
 
-    Ok(())
+    result
 }
@@ -0,0 +1,310 @@
+use crate::config::{BuiltinDetector, SecretAction, SecretRule};
+
+use regex::Regex;
+
+const REDACTED_PLACEHOLDER: &str = "[REDACTED]";
+
+/// Outcome of running the secret rules on a text entry.
+#[derive(Debug, PartialEq)]
+pub enum SecretVerdict {
+    /// No rule matched, store the entry as is.
+    Clean,
+    /// A `Skip` rule matched, the entry must not be recorded.
+    Skip { rule: String },
+    /// Store `text` (possibly redacted), expiring it if an `Expire` rule matched.
+    Store { text: String, expire: bool },
+}
+
+struct CompiledRule {
+    name: String,
+    regex: Regex,
+    /// Matches must also pass the Luhn checksum (credit card numbers).
+    luhn: bool,
+    action: SecretAction,
+}
+
+/// Rule engine run on every text entry before it is inserted in the history.
+pub struct SecretFilter {
+    rules: Vec<CompiledRule>,
+}
+
+impl SecretFilter {
+    /// Compiles the configured rules. Invalid rules are logged and ignored
+    /// so a typo in a user regex does not prevent the daemon from starting.
+    pub fn new(rules: &[SecretRule]) -> Self {
+        let compiled = rules
+            .iter()
+            .filter_map(|rule| {
+                let (pattern, luhn) = match (&rule.detector, &rule.pattern) {
+                    (Some(detector), _) => (
+                        Self::builtin_pattern(*detector).to_string(),
+                        *detector == BuiltinDetector::CreditCard,
+                    ),
+                    (None, Some(pattern)) => (pattern.clone(), false),
+                    (None, None) => {
+                        tracing::error!(
+                            "Secret rule \"{}\" has neither a detector nor a pattern, ignoring it.",
+                            rule.name
+                        );
+                        return None;
+                    }
+                };
+
+                match Regex::new(&pattern) {
+                    Ok(regex) => Some(CompiledRule {
+                        name: rule.name.clone(),
+                        regex,
+                        luhn,
+                        action: rule.action,
+                    }),
+                    Err(error) => {
+                        tracing::error!(
+                            "Secret rule \"{}\" has an invalid pattern, ignoring it: {error}",
+                            rule.name
+                        );
+                        None
+                    }
+                }
+            })
+            .collect();
+
+        Self { rules: compiled }
+    }
+
+    fn builtin_pattern(detector: BuiltinDetector) -> &'static str {
+        match detector {
+            BuiltinDetector::PrivateKey => {
+                r"(?s)-----BEGIN (?:[A-Z0-9]+ )*PRIVATE KEY-----.*?(?:-----END (?:[A-Z0-9]+ )*PRIVATE KEY-----|\z)"
+            }
+            BuiltinDetector::AwsAccessKey => r"\b(?:AKIA|ASIA)[0-9A-Z]{16}\b",
+            BuiltinDetector::JsonWebToken => {
+                r"\beyJ[A-Za-z0-9_-]{5,}\.eyJ[A-Za-z0-9_-]{5,}\.[A-Za-z0-9_-]{10,}"
+            }
+            BuiltinDetector::ApiToken => {
+                r"\b(?:(?:ghp|gho|ghu|ghs|ghr|github_pat)_[A-Za-z0-9_]{20,}|sk-[A-Za-z0-9_-]{20,}|xox[abprs]-[A-Za-z0-9-]{10,}|glpat-[A-Za-z0-9_-]{20,})"
+            }
+            BuiltinDetector::CreditCard => r"\b(?:\d[ -]?){12,18}\d\b",
+            BuiltinDetector::OneTimeCode => r"\A\s*\d{6,8}\s*\z",
+        }
+    }
+
+    /// Runs every rule on `text`. `Skip` wins over everything, otherwise all
+    /// `Redact` matches are replaced and any `Expire` match flags the entry.
+    pub fn check(&self, text: &str) -> SecretVerdict {
+        let mut redacted_spans: Vec<(usize, usize)> = Vec::new();
+        let mut expire = false;
+
+        for rule in &self.rules {
+            let matches: Vec<_> = rule
+                .regex
+                .find_iter(text)
+                .filter(|found| !rule.luhn || Self::passes_luhn(found.as_str()))
+                .collect();
+
+            if matches.is_empty() {
+                continue;
+            }
+
+            match rule.action {
+                SecretAction::Skip => {
+                    return SecretVerdict::Skip {
+                        rule: rule.name.clone(),
+                    }
+                }
+                SecretAction::Redact => {
+                    redacted_spans.extend(matches.iter().map(|found| (found.start(), found.end())))
+                }
+                SecretAction::Expire => expire = true,
+            }
+        }
+
+        if redacted_spans.is_empty() && !expire {
+            return SecretVerdict::Clean;
+        }
+
+        SecretVerdict::Store {
+            text: Self::redact(text, redacted_spans),
+            expire,
+        }
+    }
+
+    fn redact(text: &str, mut spans: Vec<(usize, usize)>) -> String {
+        spans.sort_unstable();
+
+        let mut redacted = String::with_capacity(text.len());
+        let mut cursor = 0;
+        for (start, end) in spans {
+            // Overlapping matches from different rules extend the previous placeholder
+            if start < cursor {
+                cursor = cursor.max(end);
+                continue;
+            }
+            redacted.push_str(&text[cursor..start]);
+            redacted.push_str(REDACTED_PLACEHOLDER);
+            cursor = end;
+        }
+        redacted.push_str(&text[cursor..]);
+        redacted
+    }
+
+    fn passes_luhn(candidate: &str) -> bool {
+        let digits: Vec<u32> = candidate.chars().filter_map(|c| c.to_digit(10)).collect();
+        if !(13..=19).contains(&digits.len()) {
+            return false;
+        }
+
+        let checksum: u32 = digits
+            .iter()
+            .rev()
+            .enumerate()
+            .map(|(idx, digit)| {
+                if idx % 2 == 1 {
+                    let doubled = digit * 2;
+                    if doubled > 9 {
+                        doubled - 9
+                    } else {
+                        doubled
+                    }
+                } else {
+                    *digit
+                }
+            })
+            .sum();
+
+        checksum.is_multiple_of(10)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::DaemonConfig;
+
+    fn builtin(detector: BuiltinDetector, action: SecretAction) -> SecretRule {
+        SecretRule {
+            name: format!("{detector:?}"),
+            detector: Some(detector),
+            pattern: None,
+            action,
+        }
+    }
+
+    fn pattern(name: &str, pattern: &str, action: SecretAction) -> SecretRule {
+        SecretRule {
+            name: name.to_string(),
+            detector: None,
+            pattern: Some(pattern.to_string()),
+            action,
+        }
+    }
+
+    #[test]
+    fn clean_text_is_clean() {
+        let filter = SecretFilter::new(&DaemonConfig::default().secret_rules);
+        assert_eq!(filter.check("just some notes"), SecretVerdict::Clean);
+    }
+
+    #[test]
+    fn credit_card_numbers_must_pass_luhn() {
+        let filter =
+            SecretFilter::new(&[builtin(BuiltinDetector::CreditCard, SecretAction::Redact)]);
+
+        assert_eq!(
+            filter.check("card 4111 1111 1111 1111 ok"),
+            SecretVerdict::Store {
+                text: "card [REDACTED] ok".to_string(),
+                expire: false,
+            }
+        );
+        // Same length, wrong checksum: an order number, not a card
+        assert_eq!(
+            filter.check("order 4111 1111 1111 1112"),
+            SecretVerdict::Clean
+        );
+    }
+
+    #[test]
+    fn overlapping_redactions_are_merged() {
+        let filter = SecretFilter::new(&[
+            pattern("Prefix", "secret-[a-z]+", SecretAction::Redact),
+            pattern("Suffix", "[a-z]+-value", SecretAction::Redact),
+        ]);
+
+        assert_eq!(
+            filter.check("a secret-long-value here"),
+            SecretVerdict::Store {
+                text: "a [REDACTED] here".to_string(),
+                expire: false,
+            }
+        );
+    }
+
+    #[test]
+    fn skip_wins_over_redact_and_expire() {
+        let filter = SecretFilter::new(&[
+            pattern("Redacted", "token", SecretAction::Redact),
+            pattern("Expired", "token", SecretAction::Expire),
+            pattern("Skipped", "token", SecretAction::Skip),
+        ]);
+
+        assert_eq!(
+            filter.check("my token"),
+            SecretVerdict::Skip {
+                rule: "Skipped".to_string()
+            }
+        );
+    }
+
+    #[test]
+    fn redact_and_expire_combine() {
+        let filter = SecretFilter::new(&[
+            pattern("Redacted", "hunter2", SecretAction::Redact),
+            pattern("Expired", "password", SecretAction::Expire),
+        ]);
+
+        assert_eq!(
+            filter.check("password: hunter2"),
+            SecretVerdict::Store {
+                text: "password: [REDACTED]".to_string(),
+                expire: true,
+            }
+        );
+    }
+
+    #[test]
+    fn invalid_rules_are_ignored() {
+        let filter = SecretFilter::new(&[
+            pattern("Broken", "([a-z", SecretAction::Skip),
+            SecretRule {
+                name: "Empty".to_string(),
+                detector: None,
+                pattern: None,
+                action: SecretAction::Skip,
+            },
+            pattern("Valid", "itk_[0-9]+", SecretAction::Skip),
+        ]);
+
+        assert_eq!(filter.check("([a-z"), SecretVerdict::Clean);
+        assert_eq!(
+            filter.check("itk_123"),
+            SecretVerdict::Skip {
+                rule: "Valid".to_string()
+            }
+        );
+    }
+
+    #[test]
+    fn one_time_codes_expire() {
+        let filter =
+            SecretFilter::new(&[builtin(BuiltinDetector::OneTimeCode, SecretAction::Expire)]);
+
+        assert_eq!(
+            filter.check(" 123456\n"),
+            SecretVerdict::Store {
+                text: " 123456\n".to_string(),
+                expire: true,
+            }
+        );
+        assert_eq!(filter.check("call 123456 later"), SecretVerdict::Clean);
+    }
+}
@@ -0,0 +1,333 @@
+//! Inspects the current clipboard selection beyond its content,
+//! ex: the targets (MIME types) offered by the application that owns it.
+//! arboard only exposes the content, so this talks to the X server directly.
+//! On Wayland this goes through XWayland, which mirrors the clipboard.
+
+use crate::history::SourceApp;
+
+#[cfg(all(unix, not(target_os = "macos")))]
+pub use x11::SelectionInspector;
+
+impl SourceApp {
+    /// Whether any of the known names of the app matches `name`, ignoring case.
+    pub fn matches(&self, name: &str) -> bool {
+        [
+            &self.window_instance,
+            &self.window_class,
+            &self.process_name,
+        ]
+        .into_iter()
+        .flatten()
+        .any(|known| known.eq_ignore_ascii_case(name))
+    }
+}
+
+/// Targets (MIME types) offered by the clipboard owner.
+#[derive(Debug, PartialEq)]
+pub enum OfferedTargets {
+    Known(Vec<String>),
+    /// Targets are not inspected on this platform, or there is no X server.
+    Unsupported,
+    /// The owner refused or did not answer in time, so a concealment hint may be missed.
+    Unknown,
+}
+
+/// Fallback for platforms where the selection can not be inspected yet:
+/// no targets are ever reported.
+#[cfg(not(all(unix, not(target_os = "macos"))))]
+pub struct SelectionInspector;
+
+#[cfg(not(all(unix, not(target_os = "macos"))))]
+impl SelectionInspector {
+    pub fn new() -> Self {
+        Self
+    }
+
+    pub fn offered_targets(&self) -> OfferedTargets {
+        OfferedTargets::Unsupported
+    }
+
+    pub fn owner_app(&self) -> Option<SourceApp> {
+        None
+    }
+}
+
+#[cfg(all(unix, not(target_os = "macos")))]
+mod x11 {
+    use super::{OfferedTargets, SourceApp};
+
+    use anyhow::{anyhow, Context, Result};
+    use std::fs;
+    use std::thread;
+    use std::time::{Duration, Instant};
+    use x11rb::connection::Connection;
+    use x11rb::protocol::res::{ClientIdMask, ClientIdSpec, ConnectionExt as ResConnectionExt};
+    use x11rb::protocol::xproto::{
+        AtomEnum, ConnectionExt, CreateWindowAux, EventMask, PropMode, Timestamp, WindowClass,
+    };
+    use x11rb::protocol::Event;
+    use x11rb::rust_connection::RustConnection;
+    use x11rb::{COPY_DEPTH_FROM_PARENT, NONE};
+
+    /// How long the selection owner has to answer a conversion request.
+    const SELECTION_TIMEOUT_MS: u64 = 200;
+
+    struct X11Selection {
+        conn: RustConnection,
+        window: u32,
+        clipboard_atom: u32,
+        targets_atom: u32,
+        property_atom: u32,
+        timestamp_atom: u32,
+    }
+
+    pub struct SelectionInspector {
+        x11: Option<X11Selection>,
+    }
+
+    impl SelectionInspector {
+        pub fn new() -> Self {
+            match X11Selection::connect() {
+                Ok(x11) => Self { x11: Some(x11) },
+                Err(error) => {
+                    tracing::warn!(
+                        "Could not connect to the X server, clipboard targets will not be inspected: {error:#}"
+                    );
+                    Self { x11: None }
+                }
+            }
+        }
+
+        /// Names of the targets offered by the current clipboard owner.
+        pub fn offered_targets(&self) -> OfferedTargets {
+            let Some(x11) = &self.x11 else {
+                return OfferedTargets::Unsupported;
+            };
+
+            match x11.offered_targets() {
+                Ok(Some(targets)) => OfferedTargets::Known(targets),
+                Ok(None) => {
+                    tracing::debug!("The clipboard owner refused to list its targets.");
+                    OfferedTargets::Unknown
+                }
+                Err(error) => {
+                    tracing::debug!("Could not fetch clipboard targets: {error:#}");
+                    OfferedTargets::Unknown
+                }
+            }
+        }
+
+        /// The application that currently owns the clipboard, if it can be identified.
+        pub fn owner_app(&self) -> Option<SourceApp> {
+            let x11 = self.x11.as_ref()?;
+
+            match x11.owner_app() {
+                Ok(app) => app,
+                Err(error) => {
+                    tracing::debug!("Could not identify the clipboard owner: {error:#}");
+                    None
+                }
+            }
+        }
+    }
+
+    impl X11Selection {
+        fn connect() -> Result<Self> {
+            let (conn, screen_num) =
+                x11rb::connect(None).context("Could not open X11 connection.")?;
+            let screen = &conn.setup().roots[screen_num];
+
+            // Conversion results are written as a property of this invisible window.
+            let window = conn.generate_id()?;
+            conn.create_window(
+                COPY_DEPTH_FROM_PARENT,
+                window,
+                screen.root,
+                0,
+                0,
+                1,
+                1,
+                0,
+                WindowClass::INPUT_OUTPUT,
+                screen.root_visual,
+                &CreateWindowAux::new().event_mask(EventMask::PROPERTY_CHANGE),
+            )?;
+
+            let clipboard_atom = conn.intern_atom(false, b"CLIPBOARD")?.reply()?.atom;
+            let targets_atom = conn.intern_atom(false, b"TARGETS")?.reply()?.atom;
+            let property_atom = conn.intern_atom(false, b"CLIPPO_SELECTION")?.reply()?.atom;
+            let timestamp_atom = conn.intern_atom(false, b"CLIPPO_TIMESTAMP")?.reply()?.atom;
+            conn.flush()?;
+
+            Ok(Self {
+                conn,
+                window,
+                clipboard_atom,
+                targets_atom,
+                property_atom,
+                timestamp_atom,
+            })
+        }
+
+        fn owner_app(&self) -> Result<Option<SourceApp>> {
+            let owner = self
+                .conn
+                .get_selection_owner(self.clipboard_atom)?
+                .reply()?
+                .owner;
+            if owner == NONE {
+                return Ok(None);
+            }
+
+            // WM_CLASS is two nul terminated strings: instance then class.
+            // Clipboard owner windows are often hidden and may not have one.
+            let wm_class = self
+                .conn
+                .get_property(false, owner, AtomEnum::WM_CLASS, AtomEnum::STRING, 0, 256)?
+                .reply()?
+                .value;
+            let mut class_parts = wm_class
+                .split(|byte| *byte == 0)
+                .filter(|part| !part.is_empty())
+                .map(|part| String::from_utf8_lossy(part).into_owned());
+            let window_instance = class_parts.next();
+            let window_class = class_parts.next();
+
+            let process_name = self
+                .owner_pid(owner)
+                .and_then(|pid| fs::read_to_string(format!("/proc/{pid}/comm")).ok())
+                .map(|comm| comm.trim().to_string());
+
+            if window_instance.is_none() && window_class.is_none() && process_name.is_none() {
+                return Ok(None);
+            }
+
+            Ok(Some(SourceApp {
+                window_instance,
+                window_class,
+                process_name,
+            }))
+        }
+
+        /// Pid of the X client owning `window`, through the X-Resource extension.
+        /// Only available for local clients.
+        fn owner_pid(&self, window: u32) -> Option<u32> {
+            let spec = ClientIdSpec {
+                client: window,
+                mask: ClientIdMask::LOCAL_CLIENT_PID,
+            };
+            let reply = self.conn.res_query_client_ids(&[spec]).ok()?.reply().ok()?;
+
+            reply
+                .ids
+                .into_iter()
+                .find(|id| id.spec.mask == ClientIdMask::LOCAL_CLIENT_PID)
+                .and_then(|id| id.value.first().copied())
+        }
+
+        /// `None` if the owner refused the conversion.
+        fn offered_targets(&self) -> Result<Option<Vec<String>>> {
+            // Answers to earlier requests that timed out must not be taken for this one
+            while self.conn.poll_for_event()?.is_some() {}
+
+            // Owners echo the time of the request in their answer, which tells it apart
+            let time = self.server_time()?;
+            self.conn.convert_selection(
+                self.window,
+                self.clipboard_atom,
+                self.targets_atom,
+                self.property_atom,
+                time,
+            )?;
+            self.conn.flush()?;
+
+            let notify = self.wait_for_event(|event| match event {
+                Event::SelectionNotify(notify)
+                    if notify.requestor == self.window
+                        && notify.selection == self.clipboard_atom
+                        && notify.target == self.targets_atom
+                        && notify.time == time =>
+                {
+                    Some(notify.property)
+                }
+                _ => None,
+            })?;
+            match notify {
+                None => return Err(anyhow!("The clipboard owner did not answer in time.")),
+                Some(NONE) => return Ok(None),
+                Some(_) => {}
+            }
+
+            let reply = self
+                .conn
+                .get_property(
+                    true,
+                    self.window,
+                    self.property_atom,
+                    AtomEnum::ATOM,
+                    0,
+                    u32::MAX / 4,
+                )?
+                .reply()?;
+
+            let atoms: Vec<u32> = reply
+                .value32()
+                .map(|values| values.collect())
+                .unwrap_or_default();
+
+            let mut targets = Vec::with_capacity(atoms.len());
+            for atom in atoms {
+                let name = self.conn.get_atom_name(atom)?.reply()?.name;
+                targets.push(String::from_utf8_lossy(&name).into_owned());
+            }
+
+            Ok(Some(targets))
+        }
+
+        /// Current server time, from the notification of an empty property append
+        /// (ICCCM 2.1), as selection requests should not use `CurrentTime`.
+        fn server_time(&self) -> Result<Timestamp> {
+            self.conn.change_property(
+                PropMode::APPEND,
+                self.window,
+                self.timestamp_atom,
+                AtomEnum::STRING,
+                8,
+                0,
+                &[],
+            )?;
+            self.conn.flush()?;
+
+            self.wait_for_event(|event| match event {
+                Event::PropertyNotify(notify)
+                    if notify.window == self.window && notify.atom == self.timestamp_atom =>
+                {
+                    Some(notify.time)
+                }
+                _ => None,
+            })?
+            .ok_or_else(|| anyhow!("The X server did not report the time."))
+        }
+
+        /// Wait up to `SELECTION_TIMEOUT_MS` for an event accepted by `accept`,
+        /// other events are dropped.
+        fn wait_for_event<T>(&self, accept: impl Fn(&Event) -> Option<T>) -> Result<Option<T>> {
+            let deadline = Instant::now() + Duration::from_millis(SELECTION_TIMEOUT_MS);
+            loop {
+                match self.conn.poll_for_event()? {
+                    Some(event) => {
+                        if let Some(accepted) = accept(&event) {
+                            return Ok(Some(accepted));
+                        }
+                    }
+                    None => {
+                        if Instant::now() > deadline {
+                            return Ok(None);
+                        }
+                        thread::sleep(Duration::from_millis(5));
+                    }
+                }
+            }
+        }
+    }
+}
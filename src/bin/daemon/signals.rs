@@ -0,0 +1,88 @@
+//! Unix signal handling. SIGINT, SIGTERM and SIGHUP are blocked in every thread
+//! and received synchronously by a dedicated thread, so reacting to them is not
+//! limited to async-signal-safe code.
+//! Other platforms have no signal handling.
+
+pub enum Signal {
+    /// SIGINT or SIGTERM.
+    Stop,
+    /// SIGHUP.
+    Reload,
+}
+
+#[cfg(unix)]
+pub use unix::*;
+
+#[cfg(not(unix))]
+pub use fallback::*;
+
+#[cfg(unix)]
+mod unix {
+    use super::Signal;
+
+    use anyhow::Result;
+    use std::{io, mem, ptr, thread};
+
+    fn handled_signals() -> libc::sigset_t {
+        // SAFETY: the set is initialized by sigemptyset before being used.
+        unsafe {
+            let mut set = mem::zeroed();
+            libc::sigemptyset(&mut set);
+            libc::sigaddset(&mut set, libc::SIGINT);
+            libc::sigaddset(&mut set, libc::SIGTERM);
+            libc::sigaddset(&mut set, libc::SIGHUP);
+            set
+        }
+    }
+
+    /// Block the handled signals in the calling thread and the threads it spawns later.
+    /// Must be called before any other thread is spawned.
+    pub fn block() -> Result<()> {
+        let set = handled_signals();
+        // SAFETY: set is a valid signal set and the old mask is not requested.
+        let result = unsafe { libc::pthread_sigmask(libc::SIG_BLOCK, &set, ptr::null_mut()) };
+        if result != 0 {
+            return Err(io::Error::from_raw_os_error(result).into());
+        }
+
+        Ok(())
+    }
+
+    /// Call `on_signal` from a dedicated thread for every handled signal received.
+    pub fn listen(mut on_signal: impl FnMut(Signal) + Send + 'static) {
+        thread::spawn(move || {
+            let set = handled_signals();
+            loop {
+                let mut signal = 0;
+                // SAFETY: set is a valid signal set and signal a valid pointer.
+                let result = unsafe { libc::sigwait(&set, &mut signal) };
+                if result != 0 {
+                    tracing::error!(
+                        "Could not wait for signals, they will be ignored: {}",
+                        io::Error::from_raw_os_error(result)
+                    );
+                    return;
+                }
+
+                if signal == libc::SIGHUP {
+                    on_signal(Signal::Reload);
+                } else {
+                    on_signal(Signal::Stop);
+                }
+            }
+        });
+    }
+}
+
+#[cfg(not(unix))]
+mod fallback {
+    use super::Signal;
+
+    use anyhow::Result;
+
+    pub fn block() -> Result<()> {
+        Ok(())
+    }
+
+    pub fn listen(_on_signal: impl FnMut(Signal) + Send + 'static) {}
+}
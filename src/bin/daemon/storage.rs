@@ -0,0 +1,924 @@
+//! The history store, running on its own thread.
+//! It owns the history: capture and IPC tasks send it commands through a `StorageHandle`
+//! and never touch the history directly. Every change is saved to disk and published
+//! to the subscribers, whose connections are written by their own threads.
+
+use crate::config::DaemonConfig;
+use crate::history::{ClipboardHistoryEntry, ContentKind, HistoryItem};
+use crate::protocol::{EntrySelector, Event};
+use crate::util::unix_now;
+
+use anyhow::{anyhow, Context, Result};
+use serde::de::DeserializeOwned;
+use std::fs;
+use std::io::{BufReader, Write};
+use std::path::PathBuf;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::thread;
+use std::time::{Duration, Instant};
+
+const HISTORY_FILE_PATH: &str = ".clipboard_history.ron";
+/// Written first, then renamed over the history file, so a crash mid-save leaves the
+/// previous history intact.
+const HISTORY_TEMP_FILE_PATH: &str = ".clipboard_history.ron.tmp";
+/// Changes are saved this long after the first unsaved one, so a burst of copies is
+/// written once and commands do not wait for the disk.
+const SAVE_DELAY_MS: u64 = 1000;
+
+/// Outcome of `StorageHandle::record`.
+pub enum Recorded {
+    Inserted {
+        id: u64,
+    },
+    /// The same content is already in history, nothing was inserted.
+    Duplicate {
+        id: u64,
+        sensitive: bool,
+    },
+    /// The entry does not fit in the limits next to the pinned entries, which are never
+    /// evicted, so nothing was inserted.
+    Rejected,
+}
+
+/// Counts and size of the history, for the daemon status.
+pub struct HistoryStats {
+    pub text_entries: usize,
+    pub image_entries: usize,
+    pub total_bytes: usize,
+}
+
+/// Where the history is saved, resolved against the working directory of the daemon.
+pub fn history_path() -> PathBuf {
+    std::env::current_dir()
+        .map(|dir| dir.join(HISTORY_FILE_PATH))
+        .unwrap_or_else(|_| PathBuf::from(HISTORY_FILE_PATH))
+}
+
+enum Command {
+    Record {
+        item: HistoryItem,
+        reply: Sender<Recorded>,
+    },
+    GetHistory {
+        reply: Sender<Vec<HistoryItem>>,
+    },
+    GetEntry {
+        id: u64,
+        reply: Sender<Option<HistoryItem>>,
+    },
+    FindEntry {
+        entry: EntrySelector,
+        reply: Sender<Option<HistoryItem>>,
+    },
+    GetStats {
+        reply: Sender<HistoryStats>,
+    },
+    Clear {
+        reply: Sender<Result<()>>,
+    },
+    SetSensitive {
+        id: u64,
+        sensitive: bool,
+        reply: Sender<bool>,
+    },
+    SetPinned {
+        id: u64,
+        pinned: bool,
+        reply: Sender<bool>,
+    },
+    Delete {
+        id: u64,
+        reply: Sender<bool>,
+    },
+    RemoveEntry {
+        entry: ClipboardHistoryEntry,
+    },
+    Subscribe {
+        events: Sender<Event>,
+        reply: Sender<(u64, Vec<HistoryItem>)>,
+    },
+    Unsubscribe {
+        id: u64,
+    },
+    ReloadConfig {
+        config: DaemonConfig,
+    },
+    Shutdown {
+        reply: Sender<Result<()>>,
+    },
+}
+
+/// Sends commands to the history store. Cheap to clone, one per task.
+#[derive(Clone)]
+pub struct StorageHandle {
+    commands: Sender<Command>,
+}
+
+impl StorageHandle {
+    /// Insert `item` at the top of the history, unless the same content is already there.
+    /// The id of `item` is assigned by the store.
+    pub fn record(&self, item: HistoryItem) -> Result<Recorded> {
+        self.request(|reply| Command::Record { item, reply })
+    }
+
+    /// The history as listed to clients, without the image pixels.
+    pub fn history(&self) -> Result<Vec<HistoryItem>> {
+        self.request(|reply| Command::GetHistory { reply })
+    }
+
+    /// The entry with this id, image pixels included.
+    pub fn entry(&self, id: u64) -> Result<Option<HistoryItem>> {
+        self.request(|reply| Command::GetEntry { id, reply })
+    }
+
+    /// The entry at this position or with this id, without the image pixels.
+    pub fn find_entry(&self, entry: EntrySelector) -> Result<Option<HistoryItem>> {
+        self.request(|reply| Command::FindEntry { entry, reply })
+    }
+
+    pub fn stats(&self) -> Result<HistoryStats> {
+        self.request(|reply| Command::GetStats { reply })
+    }
+
+    pub fn clear(&self) -> Result<()> {
+        self.request(|reply| Command::Clear { reply })?
+    }
+
+    /// Mark or unmark an entry as sensitive. Returns false if there is no entry with this id.
+    pub fn set_sensitive(&self, id: u64, sensitive: bool) -> Result<bool> {
+        self.request(|reply| Command::SetSensitive {
+            id,
+            sensitive,
+            reply,
+        })
+    }
+
+    /// Pin or unpin an entry. Returns false if there is no entry with this id.
+    pub fn set_pinned(&self, id: u64, pinned: bool) -> Result<bool> {
+        self.request(|reply| Command::SetPinned { id, pinned, reply })
+    }
+
+    /// Remove the entry with this id. Returns false if there is none.
+    pub fn delete(&self, id: u64) -> Result<bool> {
+        self.request(|reply| Command::Delete { id, reply })
+    }
+
+    /// Remove every entry with this content, without waiting for the store.
+    pub fn remove_entry(&self, entry: ClipboardHistoryEntry) -> Result<()> {
+        self.send(Command::RemoveEntry { entry })
+    }
+
+    /// Register a subscriber, history changes will be sent to `events`.
+    /// Returns the id of the subscriber and the history at the time it was registered,
+    /// without the image pixels like the events.
+    pub fn subscribe(&self, events: Sender<Event>) -> Result<(u64, Vec<HistoryItem>)> {
+        self.request(|reply| Command::Subscribe { events, reply })
+    }
+
+    pub fn unsubscribe(&self, id: u64) -> Result<()> {
+        self.send(Command::Unsubscribe { id })
+    }
+
+    /// Apply a new config, ex: evicting entries above lowered limits.
+    pub fn reload_config(&self, config: DaemonConfig) -> Result<()> {
+        self.send(Command::ReloadConfig { config })
+    }
+
+    /// Save the history one last time, tell subscribers the daemon is stopping
+    /// and stop the store. Later commands fail.
+    pub fn shutdown(&self) -> Result<()> {
+        self.request(|reply| Command::Shutdown { reply })?
+    }
+
+    fn send(&self, command: Command) -> Result<()> {
+        self.commands
+            .send(command)
+            .map_err(|_| anyhow!("The history store has stopped."))
+    }
+
+    /// Send a command and wait for its reply.
+    fn request<T>(&self, command: impl FnOnce(Sender<T>) -> Command) -> Result<T> {
+        let (reply, response) = mpsc::channel();
+        self.send(command(reply))?;
+        response
+            .recv()
+            .map_err(|_| anyhow!("The history store has stopped."))
+    }
+}
+
+struct Subscriber {
+    id: u64,
+    events: Sender<Event>,
+}
+
+/// Evict the oldest unpinned entries until the per-type count limits
+/// and the byte budget of the config are respected.
+/// Returns the ids of the evicted entries.
+fn enforce_quotas(history: &mut Vec<HistoryItem>, config: &DaemonConfig) -> Vec<u64> {
+    let mut text_count = 0;
+    let mut image_count = 0;
+    let mut total_bytes = 0;
+    for item in history.iter() {
+        match item.kind() {
+            ContentKind::Text => text_count += 1,
+            ContentKind::Image => image_count += 1,
+        }
+        total_bytes += item.entry.size_bytes();
+    }
+
+    let mut evicted_ids = Vec::new();
+    // Walk from the oldest entry, history is ordered newest first.
+    for idx in (0..history.len()).rev() {
+        let item = &history[idx];
+        if item.pinned {
+            continue;
+        }
+
+        let over_count = match item.kind() {
+            ContentKind::Text => text_count > config.max_text_entries,
+            ContentKind::Image => image_count > config.max_image_entries,
+        };
+        if !over_count && total_bytes <= config.max_total_bytes {
+            continue;
+        }
+
+        match item.kind() {
+            ContentKind::Text => text_count -= 1,
+            ContentKind::Image => image_count -= 1,
+        }
+        total_bytes -= item.entry.size_bytes();
+        evicted_ids.push(history.remove(idx).id);
+    }
+
+    evicted_ids
+}
+
+/// Whether any of the retention rules says `item` should be dropped.
+fn is_expired(item: &HistoryItem, config: &DaemonConfig, now: u64) -> bool {
+    if item.pinned {
+        return false;
+    }
+
+    if item.expires_at.is_some_and(|expires_at| now >= expires_at) {
+        return true;
+    }
+
+    let age = now.saturating_sub(item.captured_at);
+    config
+        .retention_rules
+        .iter()
+        .any(|rule| rule.kind.applies_to(item.kind()) && age > rule.max_age_secs)
+}
+
+/// Whether `item` stays within the limits once every unpinned entry is evicted.
+fn fits_next_to_pinned(history: &[HistoryItem], item: &HistoryItem, config: &DaemonConfig) -> bool {
+    let pinned = history.iter().filter(|existing| existing.pinned);
+    let pinned_bytes: usize = pinned
+        .clone()
+        .map(|existing| existing.entry.size_bytes())
+        .sum();
+    let pinned_same_kind = pinned
+        .filter(|existing| existing.kind() == item.kind())
+        .count();
+    let max_entries = match item.kind() {
+        ContentKind::Text => config.max_text_entries,
+        ContentKind::Image => config.max_image_entries,
+    };
+
+    pinned_same_kind < max_entries
+        && pinned_bytes + item.entry.size_bytes() <= config.max_total_bytes
+}
+
+pub struct HistoryStore {
+    history: Vec<HistoryItem>,
+    config: DaemonConfig,
+    next_entry_id: u64,
+    subscribers: Vec<Subscriber>,
+    next_subscriber_id: u64,
+    /// When the unsaved changes are due to be written, `None` if everything is saved.
+    save_due: Option<Instant>,
+}
+
+impl HistoryStore {
+    /// Load the saved history and start the store thread.
+    pub fn spawn(config: DaemonConfig) -> Result<StorageHandle> {
+        // Limits may have been lowered since the history was saved
+        let mut history = Self::load_history()?;
+        enforce_quotas(&mut history, &config);
+
+        // Entries saved by older versions have no id yet
+        let mut next_entry_id = history.iter().map(|item| item.id).max().unwrap_or(0) + 1;
+        for item in history.iter_mut().filter(|item| item.id == 0) {
+            item.id = next_entry_id;
+            next_entry_id += 1;
+        }
+
+        let store = Self {
+            history,
+            config,
+            next_entry_id,
+            subscribers: Vec::new(),
+            next_subscriber_id: 1,
+            save_due: None,
+        };
+
+        let (commands, receiver) = mpsc::channel();
+        thread::spawn(move || store.run(receiver));
+
+        Ok(StorageHandle { commands })
+    }
+
+    /// Handle commands until shut down or every handle is dropped, sweeping expired entries
+    /// every `sweep_interval_secs` so expiry does not depend on clipboard activity,
+    /// and saving changes once they are due.
+    fn run(mut self, commands: Receiver<Command>) {
+        let mut last_sweep = Instant::now();
+
+        loop {
+            let sweep_interval = Duration::from_secs(self.config.sweep_interval_secs.max(1));
+            let next_sweep = last_sweep + sweep_interval;
+            let wake_up = match self.save_due {
+                Some(save_due) => save_due.min(next_sweep),
+                None => next_sweep,
+            };
+
+            match commands.recv_timeout(wake_up.saturating_duration_since(Instant::now())) {
+                Ok(Command::Shutdown { reply }) => {
+                    let _ = reply.send(self.shutdown());
+                    return;
+                }
+                Ok(command) => self.handle(command),
+                Err(RecvTimeoutError::Timeout) => {
+                    if Instant::now() >= next_sweep {
+                        self.sweep_expired_entries();
+                        last_sweep = Instant::now();
+                    }
+                    if self
+                        .save_due
+                        .is_some_and(|save_due| Instant::now() >= save_due)
+                    {
+                        self.save_pending_changes();
+                    }
+                }
+                Err(RecvTimeoutError::Disconnected) => {
+                    self.save_pending_changes();
+                    return;
+                }
+            }
+        }
+    }
+
+    // Replies are ignored if the requester is gone.
+    fn handle(&mut self, command: Command) {
+        match command {
+            Command::Record { item, reply } => {
+                let _ = reply.send(self.record(item));
+            }
+            Command::GetHistory { reply } => {
+                let _ = reply.send(self.listing());
+            }
+            Command::GetEntry { id, reply } => {
+                let item = self.history.iter().find(|item| item.id == id).cloned();
+                let _ = reply.send(item);
+            }
+            Command::FindEntry { entry, reply } => {
+                let item = match entry {
+                    EntrySelector::Position(position) => position
+                        .checked_sub(1)
+                        .and_then(|idx| self.history.get(idx)),
+                    EntrySelector::Id(id) => self.history.iter().find(|item| item.id == id),
+                };
+                let _ = reply.send(item.map(HistoryItem::without_pixels));
+            }
+            Command::GetStats { reply } => {
+                let _ = reply.send(self.stats());
+            }
+            Command::Clear { reply } => {
+                let _ = reply.send(self.clear());
+            }
+            Command::SetSensitive {
+                id,
+                sensitive,
+                reply,
+            } => {
+                let _ = reply.send(self.set_sensitive(id, sensitive));
+            }
+            Command::SetPinned { id, pinned, reply } => {
+                let _ = reply.send(self.set_pinned(id, pinned));
+            }
+            Command::Delete { id, reply } => {
+                let _ = reply.send(self.delete(id));
+            }
+            Command::RemoveEntry { entry } => self.remove_entry(&entry),
+            Command::Subscribe { events, reply } => {
+                let id = self.next_subscriber_id;
+                self.next_subscriber_id += 1;
+                self.subscribers.push(Subscriber { id, events });
+                tracing::info!(
+                    "Subscriber {id} connected, {} subscribers ...",
+                    self.subscribers.len()
+                );
+
+                let _ = reply.send((id, self.listing()));
+            }
+            Command::ReloadConfig { config } => self.reload_config(config),
+            // Handled by run, which stops afterwards
+            Command::Shutdown { reply } => {
+                let _ = reply.send(Ok(()));
+            }
+            Command::Unsubscribe { id } => {
+                let subscribers_len = self.subscribers.len();
+                self.subscribers.retain(|subscriber| subscriber.id != id);
+                if self.subscribers.len() < subscribers_len {
+                    tracing::info!(
+                        "Subscriber {id} disconnected, {} subscribers left ...",
+                        self.subscribers.len()
+                    );
+                }
+            }
+        }
+    }
+
+    fn record(&mut self, mut item: HistoryItem) -> Recorded {
+        if let Some(existing) = self
+            .history
+            .iter()
+            .find(|existing| existing.entry == item.entry)
+        {
+            return Recorded::Duplicate {
+                id: existing.id,
+                sensitive: existing.sensitive,
+            };
+        }
+
+        // Otherwise every unpinned entry would be evicted, then the new one too
+        if !fits_next_to_pinned(&self.history, &item, &self.config) {
+            return Recorded::Rejected;
+        }
+
+        // Insert new value at first index
+        let id = self.next_entry_id;
+        item.id = id;
+        self.next_entry_id += 1;
+        self.history.insert(0, item.clone());
+
+        // Keep only the wanted number of entries and bytes
+        let evicted_ids = enforce_quotas(&mut self.history, &self.config);
+
+        self.publish(
+            std::iter::once(Event::EntryAdded(item.without_pixels()))
+                .chain(evicted_ids.into_iter().map(|id| Event::EntryRemoved { id })),
+        );
+
+        self.mark_unsaved();
+
+        Recorded::Inserted { id }
+    }
+
+    /// The history without the image pixels, which would make a single message of the
+    /// whole byte budget. Clients fetch them per entry with `GetEntry`.
+    fn listing(&self) -> Vec<HistoryItem> {
+        self.history
+            .iter()
+            .map(HistoryItem::without_pixels)
+            .collect()
+    }
+
+    fn stats(&self) -> HistoryStats {
+        let image_entries = self
+            .history
+            .iter()
+            .filter(|item| item.kind() == ContentKind::Image)
+            .count();
+
+        HistoryStats {
+            text_entries: self.history.len() - image_entries,
+            image_entries,
+            total_bytes: self
+                .history
+                .iter()
+                .map(|item| item.entry.size_bytes())
+                .sum(),
+        }
+    }
+
+    fn reload_config(&mut self, config: DaemonConfig) {
+        self.config = config;
+
+        // Limits may have been lowered
+        let evicted_ids = enforce_quotas(&mut self.history, &self.config);
+        if evicted_ids.is_empty() {
+            return;
+        }
+
+        tracing::info!(
+            "Evicted {} entries above the reloaded limits ...",
+            evicted_ids.len()
+        );
+        self.publish(evicted_ids.into_iter().map(|id| Event::EntryRemoved { id }));
+        self.mark_unsaved();
+    }
+
+    fn shutdown(&mut self) -> Result<()> {
+        self.publish([Event::Stopping]);
+        // Closes the event channels, the subscriber threads end once their events are sent
+        self.subscribers.clear();
+
+        self.save_due = None;
+        self.save_history()
+            .context("Could not save history before stopping.")
+    }
+
+    fn set_sensitive(&mut self, id: u64, sensitive: bool) -> bool {
+        let Some(item) = self.history.iter_mut().find(|item| item.id == id) else {
+            return false;
+        };
+        item.sensitive = sensitive;
+        let event = Event::Updated(item.without_pixels());
+        self.publish([event]);
+
+        self.mark_unsaved();
+        true
+    }
+
+    fn set_pinned(&mut self, id: u64, pinned: bool) -> bool {
+        let Some(item) = self.history.iter_mut().find(|item| item.id == id) else {
+            return false;
+        };
+        item.pinned = pinned;
+        let event = Event::Updated(item.without_pixels());
+        self.publish([event]);
+
+        self.mark_unsaved();
+        true
+    }
+
+    fn delete(&mut self, id: u64) -> bool {
+        let history_len = self.history.len();
+        self.history.retain(|item| item.id != id);
+        if self.history.len() == history_len {
+            return false;
+        }
+        self.publish([Event::EntryRemoved { id }]);
+
+        self.mark_unsaved();
+        true
+    }
+
+    fn remove_entry(&mut self, entry: &ClipboardHistoryEntry) {
+        let removed_ids: Vec<u64> = self
+            .history
+            .iter()
+            .filter(|item| item.entry == *entry)
+            .map(|item| item.id)
+            .collect();
+        if removed_ids.is_empty() {
+            return;
+        }
+
+        self.history.retain(|item| item.entry != *entry);
+        self.publish(removed_ids.into_iter().map(|id| Event::EntryRemoved { id }));
+
+        self.mark_unsaved();
+    }
+
+    /// Drop the entries matched by the retention rules of the daemon config.
+    fn sweep_expired_entries(&mut self) {
+        let now = unix_now();
+        let expired_ids: Vec<u64> = self
+            .history
+            .iter()
+            .filter(|item| is_expired(item, &self.config, now))
+            .map(|item| item.id)
+            .collect();
+        if expired_ids.is_empty() {
+            return;
+        }
+
+        self.history
+            .retain(|item| !is_expired(item, &self.config, now));
+        tracing::info!(
+            "Dropped {} expired entries from history ...",
+            expired_ids.len()
+        );
+        self.publish(expired_ids.into_iter().map(|id| Event::EntryRemoved { id }));
+
+        self.mark_unsaved();
+    }
+
+    /// Queue events for every subscriber. The channel of a subscriber whose connection
+    /// failed is closed, so it is dropped here.
+    fn publish(&mut self, events: impl IntoIterator<Item = Event>) {
+        if self.subscribers.is_empty() {
+            return;
+        }
+
+        for event in events {
+            self.subscribers.retain(|subscriber| {
+                let connected = subscriber.events.send(event.clone()).is_ok();
+                if !connected {
+                    tracing::info!("Subscriber {} disconnected, dropping it ...", subscriber.id);
+                }
+                connected
+            });
+        }
+    }
+
+    /// Have the history saved once `SAVE_DELAY_MS` have passed since the first unsaved change.
+    fn mark_unsaved(&mut self) {
+        if self.save_due.is_none() {
+            self.save_due = Some(Instant::now() + Duration::from_millis(SAVE_DELAY_MS));
+        }
+    }
+
+    fn save_pending_changes(&mut self) {
+        if self.save_due.take().is_none() {
+            return;
+        }
+
+        match self.save_history() {
+            Ok(()) => tracing::info!("Successfully saved history ..."),
+            Err(e) => tracing::error!("An error occured when saving history to file: {e:#} ..."),
+        }
+    }
+
+    /// Save clipboard history to ron file, through a temporary file renamed over it.
+    fn save_history(&self) -> Result<()> {
+        let mut file = fs::OpenOptions::new()
+            .create(true)
+            .write(true)
+            .truncate(true)
+            .open(HISTORY_TEMP_FILE_PATH)
+            .context(format!("Could not create or open {HISTORY_TEMP_FILE_PATH}"))?;
+
+        let serialized_history = ron::ser::to_string(&self.history)
+            .context("Could not serialize history when saving to file.")?;
+
+        file.write_all(serialized_history.as_bytes())
+            .and_then(|()| file.sync_all())
+            .context(format!(
+                "Could not write serialized history to {HISTORY_TEMP_FILE_PATH}"
+            ))?;
+
+        fs::rename(HISTORY_TEMP_FILE_PATH, HISTORY_FILE_PATH).context(format!(
+            "Could not replace {HISTORY_FILE_PATH} with {HISTORY_TEMP_FILE_PATH}"
+        ))?;
+
+        Ok(())
+    }
+
+    /// Loads the current history from the file.
+    /// Older formats (bare entries, then plain strings) are migrated on the fly.
+    /// Static method.
+    fn load_history() -> Result<Vec<HistoryItem>> {
+        let typed_load_error = match Self::read_history_file::<Vec<HistoryItem>>() {
+            Ok(history) => return Ok(history),
+            Err(error) => error,
+        };
+
+        let untimed_load_error = match Self::read_history_file::<Vec<ClipboardHistoryEntry>>() {
+            Ok(untimed_history) => {
+                tracing::warn!(
+                    "Loaded clipboard history without metadata in daemon; data will be migrated on next save."
+                );
+                return Ok(untimed_history.into_iter().map(HistoryItem::new).collect());
+            }
+            Err(error) => error,
+        };
+
+        match Self::read_history_file::<Vec<String>>() {
+            Ok(legacy_history) => {
+                tracing::warn!(
+                    "Loaded legacy string-only clipboard history format in daemon; data will be migrated on next save."
+                );
+                Ok(legacy_history
+                    .into_iter()
+                    .map(|text| HistoryItem::new(ClipboardHistoryEntry::Text(text)))
+                    .collect())
+            }
+            Err(load_error) => {
+                eprintln!(
+                    "Could not load typed history: {typed_load_error}\nCould not load untimed history: {untimed_load_error}\nCould not load legacy history: {load_error}\nFalling back to an empty history.\n",
+                );
+                Ok(Vec::new())
+            }
+        }
+    }
+
+    fn read_history_file<T: DeserializeOwned>() -> Result<T> {
+        fs::File::open(HISTORY_FILE_PATH)
+            .context(format!("Could not open \"{HISTORY_FILE_PATH}\""))
+            .and_then(|file| {
+                let reader = BufReader::new(file);
+                ron::de::from_reader(reader).context("Error deserializing clipboard history.")
+            })
+    }
+
+    fn clear(&mut self) -> Result<()> {
+        self.history.clear(); // Clear history in memory
+        self.publish([Event::Cleared]);
+        // Nothing left to save, the file is removed instead
+        self.save_due = None;
+
+        match fs::remove_file(HISTORY_FILE_PATH) {
+            Ok(()) => {}
+            Err(error) if error.kind() == std::io::ErrorKind::NotFound => {}
+            Err(error) => {
+                return Err(anyhow!("Could not delete the history file: {error}"));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{EntryKind, RetentionRule};
+    use crate::history::ClipboardImageEntry;
+
+    const NOW: u64 = 1_000_000;
+
+    fn text(id: u64, text: &str) -> HistoryItem {
+        HistoryItem {
+            id,
+            captured_at: NOW,
+            ..HistoryItem::new(ClipboardHistoryEntry::Text(text.to_string()))
+        }
+    }
+
+    /// A 1x1 image, 4 bytes.
+    fn image(id: u64) -> HistoryItem {
+        let image = ClipboardImageEntry {
+            width: 1,
+            height: 1,
+            bytes: vec![0; 4],
+        };
+        HistoryItem {
+            id,
+            captured_at: NOW,
+            ..HistoryItem::new(ClipboardHistoryEntry::Image(image))
+        }
+    }
+
+    fn aged(mut item: HistoryItem, age_secs: u64) -> HistoryItem {
+        item.captured_at = NOW - age_secs;
+        item
+    }
+
+    fn rule(kind: EntryKind, max_age_secs: u64) -> RetentionRule {
+        RetentionRule { kind, max_age_secs }
+    }
+
+    fn limits(
+        max_text_entries: usize,
+        max_image_entries: usize,
+        max_total_bytes: usize,
+    ) -> DaemonConfig {
+        DaemonConfig {
+            max_text_entries,
+            max_image_entries,
+            max_total_bytes,
+            ..DaemonConfig::default()
+        }
+    }
+
+    fn ids(history: &[HistoryItem]) -> Vec<u64> {
+        history.iter().map(|item| item.id).collect()
+    }
+
+    /// A store that is not running, so its methods can be called directly.
+    fn store(history: Vec<HistoryItem>, config: DaemonConfig) -> HistoryStore {
+        HistoryStore {
+            next_entry_id: history.iter().map(|item| item.id).max().unwrap_or(0) + 1,
+            history,
+            config,
+            subscribers: Vec::new(),
+            next_subscriber_id: 1,
+            save_due: None,
+        }
+    }
+
+    #[test]
+    fn count_limits_apply_per_kind() {
+        // Newest first
+        let mut history = vec![text(5, "c"), image(4), text(3, "b"), image(2), text(1, "a")];
+
+        let evicted_ids = enforce_quotas(&mut history, &limits(2, 1, usize::MAX));
+
+        assert_eq!(evicted_ids, vec![1, 2]);
+        assert_eq!(ids(&history), vec![5, 4, 3]);
+    }
+
+    #[test]
+    fn byte_budget_evicts_the_oldest_entries() {
+        let mut history = vec![text(3, "ccc"), text(2, "bbb"), image(1)];
+
+        let evicted_ids = enforce_quotas(&mut history, &limits(10, 10, 6));
+
+        assert_eq!(evicted_ids, vec![1]);
+        assert_eq!(ids(&history), vec![3, 2]);
+    }
+
+    #[test]
+    fn pinned_entries_survive_eviction() {
+        let mut oldest = text(1, "aaaa");
+        oldest.pinned = true;
+        let mut history = vec![text(3, "cccc"), text(2, "bbbb"), oldest];
+
+        let evicted_ids = enforce_quotas(&mut history, &limits(2, 10, 8));
+
+        assert_eq!(evicted_ids, vec![2]);
+        assert_eq!(ids(&history), vec![3, 1]);
+    }
+
+    #[test]
+    fn entry_is_inserted_next_to_pinned_ones_if_it_fits() {
+        let mut pinned = text(1, "aaaa");
+        pinned.pinned = true;
+        let mut store = store(vec![text(2, "bbbb"), pinned], limits(2, 10, 8));
+
+        assert!(matches!(
+            store.record(text(0, "cccc")),
+            Recorded::Inserted { id: 3 }
+        ));
+        assert_eq!(ids(&store.history), vec![3, 1]);
+    }
+
+    #[test]
+    fn entry_is_rejected_when_pinned_ones_leave_no_room() {
+        let mut pinned = text(1, "aaaa");
+        pinned.pinned = true;
+
+        let mut by_count = store(vec![pinned.clone()], limits(1, 10, usize::MAX));
+        assert!(matches!(by_count.record(text(0, "b")), Recorded::Rejected));
+        // Other kinds have their own count
+        assert!(matches!(
+            by_count.record(image(0)),
+            Recorded::Inserted { .. }
+        ));
+
+        let mut by_bytes = store(vec![pinned], limits(10, 10, 6));
+        assert!(matches!(
+            by_bytes.record(text(0, "bbb")),
+            Recorded::Rejected
+        ));
+        assert_eq!(ids(&by_bytes.history), vec![1]);
+        assert!(by_bytes.save_due.is_none());
+    }
+
+    #[test]
+    fn no_rule_keeps_everything() {
+        let config = DaemonConfig::default();
+        assert!(!is_expired(&aged(text(1, "old"), 1_000), &config, NOW));
+    }
+
+    #[test]
+    fn kind_specific_rule_only_drops_its_kind() {
+        let config = DaemonConfig {
+            retention_rules: vec![rule(EntryKind::Image, 60)],
+            ..DaemonConfig::default()
+        };
+
+        assert!(is_expired(&aged(image(1), 61), &config, NOW));
+        assert!(!is_expired(&aged(image(1), 60), &config, NOW));
+        assert!(!is_expired(&aged(text(2, "old"), 1_000), &config, NOW));
+    }
+
+    #[test]
+    fn any_rule_drops_every_kind() {
+        let config = DaemonConfig {
+            retention_rules: vec![rule(EntryKind::Text, 1_000), rule(EntryKind::Any, 60)],
+            ..DaemonConfig::default()
+        };
+
+        assert!(is_expired(&aged(text(1, "old"), 61), &config, NOW));
+        assert!(is_expired(&aged(image(2), 61), &config, NOW));
+        assert!(!is_expired(&aged(text(3, "new"), 10), &config, NOW));
+    }
+
+    #[test]
+    fn pinned_entries_never_expire() {
+        let config = DaemonConfig {
+            retention_rules: vec![rule(EntryKind::Any, 60)],
+            ..DaemonConfig::default()
+        };
+        let mut item = aged(text(1, "old"), 1_000);
+        item.pinned = true;
+        item.expires_at = Some(NOW - 1);
+
+        assert!(!is_expired(&item, &config, NOW));
+    }
+
+    #[test]
+    fn secret_expiry_applies_without_rules() {
+        let config = DaemonConfig::default();
+        let mut item = text(1, "123456");
+
+        item.expires_at = Some(NOW + 1);
+        assert!(!is_expired(&item, &config, NOW));
+        item.expires_at = Some(NOW);
+        assert!(is_expired(&item, &config, NOW));
+    }
+}
@@ -1,43 +1,23 @@
+use crate::client::DaemonClient;
 use crate::config::ClippoConfig;
-use crate::DAEMON_LISTENING_PORT;
-use crate::DAEMON_SENDING_PORT;
+use crate::history::{CaptureState, HistoryItem};
+use crate::ipc;
+use crate::protocol::{DaemonStatus, Event, Request, Response};
+use crate::util::unix_now;
 use anyhow::{anyhow, Context, Result};
-use arboard::{Clipboard, Error as ClipboardError, ImageData};
-use ron::de::from_str;
-use serde::{Deserialize, Serialize};
-use std::borrow::Cow;
-use std::io::{Read, Write};
-use std::net::{TcpListener, TcpStream};
+use eframe::egui;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{Arc, Mutex};
 use std::thread;
 use std::time::{Duration, Instant};
 
-#[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
-pub enum ClipboardHistoryEntry {
-    Text(String),
-    Image(ClipboardImageEntry),
-}
-
-#[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
-pub struct ClipboardImageEntry {
-    pub width: usize,
-    pub height: usize,
-    pub bytes: Vec<u8>,
-}
-
-impl ClipboardImageEntry {
-    fn to_image_data(&self) -> ImageData<'_> {
-        ImageData {
-            width: self.width,
-            height: self.height,
-            bytes: Cow::Borrowed(&self.bytes),
-        }
-    }
-}
+const STATUS_REFRESH_SECS: u64 = 5;
+const RECONNECT_BASE_DELAY_MS: u64 = 500;
+const RECONNECT_MAX_DELAY_MS: u64 = 30_000;
 
 #[derive(Clone)]
 pub struct ClippoApp {
-    pub history_cache: Arc<Mutex<Vec<ClipboardHistoryEntry>>>,
+    pub history_cache: Arc<Mutex<Vec<HistoryItem>>>,
     pub search_query: String,
     pub config: ClippoConfig,
     pub style_needs_update: bool,
@@ -45,13 +25,21 @@ pub struct ClippoApp {
     pub confirm_clear: bool,
     pub search_focus_requested: bool,
     pub selected_entry_index: Option<usize>,
+    /// Sensitive entry currently revealed by holding its reveal control.
+    pub revealed_entry_id: Option<u64>,
+    /// Last status reported by the daemon, `None` if it could not be reached.
+    /// Also the source of the capture state.
+    pub daemon_status: Arc<Mutex<Option<DaemonStatus>>>,
+    /// Whether the history subscription is up, the history is stale otherwise.
+    pub daemon_connected: Arc<AtomicBool>,
 }
 
 impl ClippoApp {
     pub fn new() -> Self {
-        let empty_cache: Vec<ClipboardHistoryEntry> = Vec::new();
+        // Filled by the history subscription, see listen_for_history_updates
+        let empty_cache: Vec<HistoryItem> = Vec::new();
 
-        let clippo = ClippoApp {
+        ClippoApp {
             history_cache: Arc::new(Mutex::new(empty_cache)),
             search_query: String::new(),
             config: confy::load("clippo", None).unwrap_or_default(),
@@ -60,13 +48,10 @@ impl ClippoApp {
             confirm_clear: false,
             search_focus_requested: false,
             selected_entry_index: None,
-        };
-
-        if let Err(initial_history_error) = clippo.fill_initial_history() {
-            tracing::error!("An error occured when loading initial history in Clippo UI: {initial_history_error}.");
+            revealed_entry_id: None,
+            daemon_status: Arc::new(Mutex::new(None)),
+            daemon_connected: Arc::new(AtomicBool::new(false)),
         }
-
-        clippo
     }
 
     /// This method is used inside the UI (preferences)
@@ -79,6 +64,7 @@ impl ClippoApp {
             "dark_mode",
             "max_entry_display_length",
             "enable_search",
+            "search_sensitive_entries",
         ];
 
         if !allowed_settings.contains(&field_name) {
@@ -93,163 +79,174 @@ impl ClippoApp {
         tracing::info!("{field_name} changed in config.");
     }
 
-    pub fn copy_to_clipboard(&self, value: &ClipboardHistoryEntry) -> Result<()> {
-        const CLIPBOARD_WRITE_RETRIES: usize = 6;
-        const CLIPBOARD_RETRY_DELAY_MS: u64 = 50;
-
-        if let ClipboardHistoryEntry::Image(image) = value {
-            let expected_len = image
-                .width
-                .checked_mul(image.height)
-                .and_then(|pixels| pixels.checked_mul(4))
-                .ok_or_else(|| anyhow!("Image dimensions are too large to compute byte length."))?;
-
-            if image.bytes.len() != expected_len {
-                return Err(anyhow!(
-                    "Image buffer has invalid length: expected {expected_len} bytes, got {} bytes.",
-                    image.bytes.len()
-                ));
-            }
-        }
+    /// Have the daemon put the entry on the clipboard, it keeps serving it after the UI exits.
+    /// The history only lists images by their size, the daemon has their pixels.
+    pub fn copy_to_clipboard(&self, id: u64) -> Result<()> {
+        DaemonClient::connect()?.request_ok(Request::SetClipboard { id })?;
+        tracing::info!("Successfully set value to clipboard.");
+        Ok(())
+    }
 
-        let mut last_error = None;
-        for _ in 0..CLIPBOARD_WRITE_RETRIES {
-            let write_result = (|| -> Result<()> {
-                let mut clipboard =
-                    Clipboard::new().context("Could not initialize clipboard backend.")?;
-                match value {
-                    ClipboardHistoryEntry::Text(text) => clipboard
-                        .set_text(text)
-                        .context("Could not set clipboard text value.")?,
-                    ClipboardHistoryEntry::Image(image) => clipboard
-                        .set_image(image.to_image_data())
-                        .context("Could not set clipboard image value.")?,
-                }
-                Ok(())
-            })();
+    pub fn set_last_action<S: Into<String>>(&mut self, message: S) {
+        self.last_action = Some((message.into(), Instant::now()));
+    }
 
-            match write_result {
-                Ok(()) => {
-                    tracing::info!("Successfully set value to clipboard.");
-                    return Ok(());
+    /// Subscribe to the daemon history events in a background thread,
+    /// keeping the history cache up to date.
+    /// When the daemon can not be reached, the subscription is retried with an exponential
+    /// backoff. Every subscription starts with the full history, so the cache is resynced
+    /// once the daemon is back.
+    /// The window is repainted through `ctx` whenever the history or the connection changes.
+    pub fn listen_for_history_updates(self: Arc<Self>, ctx: egui::Context) {
+        let clippo_app = Arc::clone(&self);
+        thread::spawn(move || {
+            let mut delay = Duration::from_millis(RECONNECT_BASE_DELAY_MS);
+            loop {
+                let result = clippo_app.follow_history_events(&ctx);
+                let was_connected = clippo_app.daemon_connected.swap(false, Ordering::AcqRel);
+                if was_connected {
+                    ctx.request_repaint();
+                    delay = Duration::from_millis(RECONNECT_BASE_DELAY_MS);
                 }
-                Err(error) => {
-                    let is_occupied = error
-                        .downcast_ref::<ClipboardError>()
-                        .map(|clipboard_error| {
-                            matches!(clipboard_error, ClipboardError::ClipboardOccupied)
-                        })
-                        .unwrap_or(false);
-                    last_error = Some(error);
-                    if is_occupied {
-                        thread::sleep(Duration::from_millis(CLIPBOARD_RETRY_DELAY_MS));
-                        continue;
+
+                if let Err(e) = result {
+                    // Only log the first failure, not every retry
+                    if was_connected || delay == Duration::from_millis(RECONNECT_BASE_DELAY_MS) {
+                        tracing::error!("Stopped receiving history updates from the daemon: {e:#}");
                     }
-                    break;
                 }
+
+                tracing::debug!("Reconnecting to the daemon in {} ms ...", delay.as_millis());
+                thread::sleep(delay);
+                delay = (delay * 2).min(Duration::from_millis(RECONNECT_MAX_DELAY_MS));
+            }
+        });
+    }
+
+    /// Refresh the daemon status every `STATUS_REFRESH_SECS` in a background thread,
+    /// for the daemon indicator and the capture state.
+    pub fn listen_for_daemon_status(self: Arc<Self>, ctx: egui::Context) {
+        let clippo_app = Arc::clone(&self);
+        thread::spawn(move || loop {
+            if let Err(e) = clippo_app.refresh_daemon_status(&ctx) {
+                tracing::error!("{e:#}");
+                return;
             }
-        }
 
-        Err(last_error.unwrap_or_else(|| anyhow!("Could not set clipboard value after retries.")))
+            thread::sleep(Duration::from_secs(STATUS_REFRESH_SECS));
+        });
     }
 
-    pub fn preview_entry(&self, value: &ClipboardHistoryEntry) -> String {
-        match value {
-            ClipboardHistoryEntry::Text(text) => {
-                let flat = text.replace('\n', " ").replace('\r', "");
-                if flat.chars().count() > self.config.max_entry_display_length {
-                    let truncated: String = flat
-                        .chars()
-                        .take(self.config.max_entry_display_length)
-                        .collect();
-                    format!("{truncated}...")
-                } else {
-                    flat
-                }
+    /// Fetch the daemon status, the window is only repainted when it changed.
+    fn refresh_daemon_status(&self, ctx: &egui::Context) -> Result<()> {
+        let status = match DaemonClient::connect()
+            .and_then(|mut client| client.request(Request::GetStatus))
+        {
+            Ok(Response::Status(status)) => Some(status),
+            Ok(response) => {
+                tracing::error!("Unexpected response to status request: {response:?}");
+                None
             }
-            ClipboardHistoryEntry::Image(image) => {
-                format!("Image ({}x{})", image.width, image.height)
+            Err(e) => {
+                tracing::debug!("Could not fetch daemon status: {e:#}");
+                None
+            }
+        };
+
+        let mut daemon_status = self
+            .daemon_status
+            .lock()
+            .map_err(|e| anyhow!("Could not acquire daemon status lock: {}", e))?;
+        // The uptime always changes, it is shown fresh on the next repaint anyway
+        let changed = match (&*daemon_status, &status) {
+            (Some(old), Some(new)) => {
+                DaemonStatus {
+                    uptime_secs: new.uptime_secs,
+                    ..old.clone()
+                } != *new
             }
+            (old, new) => old.is_some() != new.is_some(),
+        };
+        *daemon_status = status;
+        if changed {
+            ctx.request_repaint();
         }
-    }
 
-    pub fn set_last_action<S: Into<String>>(&mut self, message: S) {
-        self.last_action = Some((message.into(), Instant::now()));
+        Ok(())
     }
 
-    pub fn listen_for_history_updates(self: Arc<Self>) {
-        let clippo_app = Arc::clone(&self);
-        thread::spawn(move || -> Result<()> {
-            let listener = TcpListener::bind(format!("127.0.0.1:{DAEMON_LISTENING_PORT}"))
-                .context(format!(
-                    "Could not bind to 127.0.0.1:{DAEMON_LISTENING_PORT} when trying to listen for daemon history updates."
-                ))?;
-
-            tracing::info!("UI server listening on port {DAEMON_LISTENING_PORT} ...");
-
-            for stream in listener.incoming() {
-                match stream {
-                    Ok(mut stream) => {
-                        let mut buffer = Vec::new();
-
-                        stream
-                            .read_to_end(&mut buffer)
-                            .context("Failed to read from stream")?;
-                        let request = String::from_utf8_lossy(&buffer);
-
-                        let mut history = clippo_app
-                            .history_cache
-                            .lock()
-                            .map_err(|e| anyhow!("Could not acquire history lock: {}", e))?;
-
-                        *history = Self::parse_history_payload(&request)?;
-                    }
-                    Err(e) => {
-                        tracing::error!(
-                            "Failed to accept connexion on {DAEMON_LISTENING_PORT}: {e} ..."
-                        );
-                    }
-                }
+    /// Bring the window to the front whenever another launch of the UI connects
+    /// to the UI socket, instead of opening a second window.
+    pub fn listen_for_raise_requests(ctx: egui::Context) {
+        thread::spawn(move || {
+            if let Err(e) = Self::serve_raise_requests(&ctx) {
+                tracing::error!("Stopped listening for other UI launches: {e:#}");
             }
-            Ok(())
         });
     }
 
-    /// Fetch the initial history from the daemon with a
-    /// TCP request. Uses an empty history if it fails.
-    fn fill_initial_history(&self) -> Result<()> {
-        let request_result = (|| -> Result<String> {
-            let mut stream = TcpStream::connect(format!("127.0.0.1:{DAEMON_SENDING_PORT}"))
-                .context(format!(
-                "Initial history request could not bind to \"127.0.0.1:{DAEMON_SENDING_PORT}\"."
-            ))?;
+    fn serve_raise_requests(ctx: &egui::Context) -> Result<()> {
+        let listener = ipc::bind(ipc::UI_SOCKET)?;
+        for stream in listener.incoming() {
+            let stream = stream.context("Could not accept UI connection.")?;
+            if let Err(e) = ipc::check_peer(&stream) {
+                tracing::warn!("{e:#}");
+                continue;
+            }
 
-            stream
-                .write_all("GET_HISTORY\n".as_bytes())
-                .context("Failed to write to stream when trying to get initial history.")?;
+            tracing::info!("Another launch of the UI asked for the window ...");
+            ctx.send_viewport_cmd(egui::ViewportCommand::Minimized(false));
+            ctx.send_viewport_cmd(egui::ViewportCommand::Visible(true));
+            ctx.send_viewport_cmd(egui::ViewportCommand::Focus);
+            ctx.request_repaint();
+        }
 
-            // Read the server's response into a string.
-            let mut response = String::new();
-            stream
-                .read_to_string(&mut response)
-                .context("Failed to read from stream when trying to get initial history.")?;
+        Ok(())
+    }
 
-            Ok(response)
-        })();
+    fn follow_history_events(&self, ctx: &egui::Context) -> Result<()> {
+        let mut client = DaemonClient::connect()?;
 
-        let mut history = self
+        let items = client.subscribe()?;
+        *self
             .history_cache
             .lock()
-            .map_err(|e| anyhow!("Could not acquire history lock: {}", e))?;
-
-        if let Ok(old_history) = request_result {
-            *history = Self::parse_history_payload(&old_history)?;
-        } else {
-            history.clear();
-            tracing::error!("Could not fetch history from clipboard daemon.\nFalling back to an empty history.\n");
+            .map_err(|e| anyhow!("Could not acquire history lock: {}", e))? = items;
+        self.daemon_connected.store(true, Ordering::Release);
+        // The daemon may have restarted, ex: its capture state is back to recording
+        self.refresh_daemon_status(ctx)?;
+        ctx.request_repaint();
+        tracing::info!(
+            "Subscribed to daemon history updates on {} ...",
+            ipc::address(ipc::DAEMON_SOCKET)
+        );
+
+        while let Some(event) = client.next_event()? {
+            let mut history = self
+                .history_cache
+                .lock()
+                .map_err(|e| anyhow!("Could not acquire history lock: {}", e))?;
+
+            match event {
+                Event::EntryAdded(item) => {
+                    history.retain(|existing| existing.id != item.id);
+                    history.insert(0, item);
+                }
+                Event::EntryRemoved { id } => history.retain(|item| item.id != id),
+                Event::Updated(item) => {
+                    if let Some(existing) =
+                        history.iter_mut().find(|existing| existing.id == item.id)
+                    {
+                        *existing = item;
+                    }
+                }
+                Event::Cleared => history.clear(),
+                Event::Stopping => tracing::info!("The daemon is stopping ..."),
+            }
+            ctx.request_repaint();
         }
-        tracing::info!("Successfully loaded initial history from clipboard daemon ...");
-        Ok(())
+
+        Err(anyhow!("The daemon closed the connection."))
     }
 
     pub fn clear_history(&mut self) -> Result<()> {
@@ -260,49 +257,78 @@ impl ClippoApp {
 
         history.clear();
 
-        let request_result = (|| -> Result<String> {
-            let mut stream = TcpStream::connect(format!("127.0.0.1:{DAEMON_SENDING_PORT}"))
-                .context(format!(
-                    "Clear history request could not bind to \"127.0.0.1:{DAEMON_SENDING_PORT}\"."
-                ))?;
-
-            // Send the RESET_HISTORY request to the server
-            stream
-                .write_all("RESET_HISTORY\n".as_bytes())
-                .context("Failed to write to stream when trying to clear history.")?;
-
-            // Read the server's response into a string.
-            let mut response = String::new();
-            stream
-                .read_to_string(&mut response)
-                .context("Failed to read from stream when trying to clear history.")?;
-            Ok(response)
-        })();
+        let request_result =
+            DaemonClient::connect().and_then(|mut client| client.request_ok(Request::ResetHistory));
 
         if let Err(e) = request_result {
-            tracing::error!("Could not clear history: {e}\n");
+            tracing::error!("Could not clear history: {e:#}\n");
         }
 
         Ok(())
     }
 
-    fn parse_history_payload(payload: &str) -> Result<Vec<ClipboardHistoryEntry>> {
-        match from_str::<Vec<ClipboardHistoryEntry>>(payload) {
-            Ok(entries) => Ok(entries),
-            Err(primary_parse_error) => match from_str::<Vec<String>>(payload) {
-                Ok(legacy_entries) => {
-                    tracing::warn!(
-                        "Loaded legacy string-only clipboard history format in UI; consider restarting daemon to migrate."
-                    );
-                    Ok(legacy_entries
-                        .into_iter()
-                        .map(ClipboardHistoryEntry::Text)
-                        .collect())
-                }
-                Err(_) => Err(anyhow!(
-                    "Failed to parse clipboard history payload: {primary_parse_error}"
-                )),
-            },
+    /// Mark or unmark an entry as sensitive in the daemon, then in the local cache.
+    pub fn set_entry_sensitive(&mut self, id: u64, sensitive: bool) -> Result<()> {
+        DaemonClient::connect()?.request_ok(Request::SetSensitive { id, sensitive })?;
+
+        let mut history = self
+            .history_cache
+            .lock()
+            .map_err(|e| anyhow!("Could not acquire history lock: {}", e))?;
+        if let Some(item) = history.iter_mut().find(|item| item.id == id) {
+            item.sensitive = sensitive;
+        }
+
+        Ok(())
+    }
+
+    /// Pause clipboard capture in the daemon, for `duration_secs` or until resumed.
+    pub fn pause_capture(&mut self, duration_secs: Option<u64>) -> Result<()> {
+        DaemonClient::connect()?.request_ok(Request::Pause { duration_secs })?;
+
+        self.set_capture_state(CaptureState::Paused(
+            duration_secs.map(|secs| unix_now().saturating_add(secs)),
+        ))
+    }
+
+    pub fn resume_capture(&mut self) -> Result<()> {
+        DaemonClient::connect()?.request_ok(Request::Resume)?;
+
+        self.set_capture_state(CaptureState::Recording)
+    }
+
+    /// Show a pause or resume right away, the next status refresh confirms it.
+    fn set_capture_state(&self, capture_state: CaptureState) -> Result<()> {
+        let mut daemon_status = self
+            .daemon_status
+            .lock()
+            .map_err(|e| anyhow!("Could not acquire daemon status lock: {}", e))?;
+        if let Some(status) = daemon_status.as_mut() {
+            status.capture_state = capture_state;
+        }
+        Ok(())
+    }
+
+    /// Capture state from the last daemon status, `None` if the daemon could not be reached.
+    fn capture_state(&self) -> Option<CaptureState> {
+        let daemon_status = self.daemon_status.lock().ok()?;
+        daemon_status.as_ref().map(|status| status.capture_state)
+    }
+
+    /// Whether capture is paused, taking the end of a timed pause into account.
+    pub fn is_capture_paused(&self) -> bool {
+        match self.capture_state() {
+            Some(CaptureState::Paused(None)) => true,
+            Some(CaptureState::Paused(Some(until))) => unix_now() < until,
+            _ => false,
+        }
+    }
+
+    /// Seconds left before a timed pause ends.
+    pub fn pause_remaining_secs(&self) -> Option<u64> {
+        match self.capture_state()? {
+            CaptureState::Paused(Some(until)) => Some(until.saturating_sub(unix_now())),
+            _ => None,
         }
     }
 }
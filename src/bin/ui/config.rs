@@ -5,11 +5,16 @@ const DEFAULT_MINIMIZE_ON_COPY: bool = true;
 const DEFAULT_EXIT_ON_COPY: bool = true;
 const DEFAULT_MINIMIZE_ON_CLEAR: bool = true;
 const DEFAULT_ENABLE_SEARCH: bool = true;
+const DEFAULT_SEARCH_SENSITIVE_ENTRIES: bool = false;
 
 fn default_exit_on_copy() -> bool {
     DEFAULT_EXIT_ON_COPY
 }
 
+fn default_search_sensitive_entries() -> bool {
+    DEFAULT_SEARCH_SENSITIVE_ENTRIES
+}
+
 #[derive(Clone, Serialize, Deserialize, Debug)]
 pub struct ClippoConfig {
     pub dark_mode: bool,
@@ -19,6 +24,8 @@ pub struct ClippoConfig {
     pub exit_on_copy: bool,
     pub minimize_on_clear: bool,
     pub enable_search: bool,
+    #[serde(default = "default_search_sensitive_entries")]
+    pub search_sensitive_entries: bool,
 }
 
 impl Default for ClippoConfig {
@@ -30,6 +37,7 @@ impl Default for ClippoConfig {
             exit_on_copy: DEFAULT_EXIT_ON_COPY,
             minimize_on_clear: DEFAULT_MINIMIZE_ON_CLEAR,
             enable_search: DEFAULT_ENABLE_SEARCH,
+            search_sensitive_entries: DEFAULT_SEARCH_SENSITIVE_ENTRIES,
         }
     }
 }
@@ -1,19 +1,44 @@
+#[path = "../common/client.rs"]
+mod client;
 mod clippo_app;
 mod config;
+#[path = "../common/history.rs"]
+mod history;
+#[path = "../common/ipc.rs"]
+mod ipc;
+#[path = "../common/protocol.rs"]
+mod protocol;
 mod ui;
+#[path = "../common/util.rs"]
+mod util;
 
 use std::sync::Arc;
 
 use clippo_app::ClippoApp;
 use eframe::egui;
 
-const DAEMON_LISTENING_PORT: u32 = 7878;
-const DAEMON_SENDING_PORT: u32 = 7879;
-
 fn main() -> eframe::Result<()> {
     // Init logging
     tracing_subscriber::fmt::init();
 
+    // Held until the window closes, a second launch raises this window instead
+    let _instance_lock = match ipc::lock_instance(ipc::UI_LOCK) {
+        Ok(Some(lock)) => Some(lock),
+        Ok(None) => {
+            match ipc::connect(ipc::UI_SOCKET) {
+                Ok(_) => tracing::info!("Clippo is already running, raising its window ..."),
+                Err(e) => {
+                    tracing::error!("Clippo is already running but could not be raised: {e:#}")
+                }
+            }
+            return Ok(());
+        }
+        Err(e) => {
+            tracing::error!("Could not check for another running Clippo, starting anyway: {e:#}");
+            None
+        }
+    };
+
     let options = eframe::NativeOptions {
         viewport: egui::ViewportBuilder::default()
             .with_inner_size([330., 460.])
@@ -27,9 +52,6 @@ fn main() -> eframe::Result<()> {
     // Create a ClippoApp instance normally (not wrapped in an Arc).
     let clippo_ui = Arc::new(ClippoApp::new());
 
-    // Spawn a background thread that periodically updates the shared history.
-    Arc::clone(&clippo_ui).listen_for_history_updates();
-
     tracing::info!("Starting App ...");
 
     // Pass the ClippoApp instance directly to run_native.
@@ -37,6 +59,13 @@ fn main() -> eframe::Result<()> {
         "Clippo",
         options,
         // We clone the inner value of Arc<ClippoApp> because Arc<ClippoApp> does not implement eframe::App
-        Box::new(move |_cc| Ok(Box::new((*clippo_ui).clone()))),
+        Box::new(move |cc| {
+            // Background threads updating the shared history and status, they repaint
+            // the window through its context when something changed.
+            Arc::clone(&clippo_ui).listen_for_history_updates(cc.egui_ctx.clone());
+            Arc::clone(&clippo_ui).listen_for_daemon_status(cc.egui_ctx.clone());
+            ClippoApp::listen_for_raise_requests(cc.egui_ctx.clone());
+            Ok(Box::new((*clippo_ui).clone()))
+        }),
     )
 }
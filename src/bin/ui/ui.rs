@@ -1,8 +1,15 @@
-use crate::clippo_app::{ClipboardHistoryEntry, ClippoApp};
+use crate::clippo_app::ClippoApp;
+use crate::history::{CaptureState, ClipboardHistoryEntry};
+use crate::protocol::DaemonStatus;
+use crate::util::{format_duration, format_size, unix_now};
 
 use eframe::egui;
+use std::sync::atomic::Ordering;
 use std::time::Duration;
 
+/// How long the message of the last action stays in the footer.
+const LAST_ACTION_DISPLAY_SECS: u64 = 4;
+
 impl eframe::App for ClippoApp {
     // Handles UI updates.
     fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
@@ -44,31 +51,37 @@ impl eframe::App for ClippoApp {
             String::new()
         };
 
-        let (total_entries, filtered_history) = if let Ok(history) = self.history_cache.lock() {
-            let total = history.len();
-            let filtered = history
-                .iter()
-                .filter(|entry| {
-                    if normalized_query.is_empty() {
-                        return true;
-                    }
+        let (total_entries, total_bytes, filtered_history) =
+            if let Ok(history) = self.history_cache.lock() {
+                let total = history.len();
+                let bytes = history.iter().map(|item| item.entry.size_bytes()).sum();
+                let filtered = history
+                    .iter()
+                    .filter(|item| {
+                        if normalized_query.is_empty() {
+                            return true;
+                        }
 
-                    match entry {
-                        ClipboardHistoryEntry::Text(value) => {
-                            value.to_lowercase().contains(&normalized_query)
+                        if item.sensitive && !self.config.search_sensitive_entries {
+                            return false;
                         }
-                        ClipboardHistoryEntry::Image(image) => {
-                            format!("image {}x{}", image.width, image.height)
-                                .contains(&normalized_query)
+
+                        match &item.entry {
+                            ClipboardHistoryEntry::Text(value) => {
+                                value.to_lowercase().contains(&normalized_query)
+                            }
+                            ClipboardHistoryEntry::Image(image) => {
+                                format!("image {}x{}", image.width, image.height)
+                                    .contains(&normalized_query)
+                            }
                         }
-                    }
-                })
-                .cloned()
-                .collect::<Vec<_>>();
-            (total, filtered)
-        } else {
-            (0, Vec::new())
-        };
+                    })
+                    .cloned()
+                    .collect::<Vec<_>>();
+                (total, bytes, filtered)
+            } else {
+                (0, 0, Vec::new())
+            };
         let filtered_entries = filtered_history.len();
         let search_input_id = egui::Id::new("search_input");
 
@@ -109,7 +122,7 @@ impl eframe::App for ClippoApp {
             if ctx.input(|input| input.key_pressed(egui::Key::Enter)) {
                 if let Some(selected_idx) = self.selected_entry_index {
                     if let Some(selected_value) = filtered_history.get(selected_idx).cloned() {
-                        if let Err(error) = self.copy_to_clipboard(&selected_value) {
+                        if let Err(error) = self.copy_to_clipboard(selected_value.id) {
                             tracing::error!("Could not copy selected entry with Enter: {error:#}");
                             self.set_last_action("Failed to copy entry to clipboard.");
                         } else {
@@ -126,13 +139,36 @@ impl eframe::App for ClippoApp {
             }
         }
 
+        let daemon_connected = self.daemon_connected.load(Ordering::Acquire);
+
         egui::TopBottomPanel::top("top_panel").show(ctx, |ui| {
             ui.add_space(4.0);
             ui.horizontal(|ui| {
                 ui.heading("Clippo");
+                let daemon_status = self
+                    .daemon_status
+                    .lock()
+                    .ok()
+                    .and_then(|status| status.clone());
+                daemon_indicator(ui, daemon_connected, daemon_status.as_ref());
                 ui.label(
                     egui::RichText::new(format!("{filtered_entries}/{total_entries} shown")).weak(),
                 );
+                ui.label(egui::RichText::new(format_size(total_bytes)).weak())
+                    .on_hover_text("Size of the clipboard history");
+                if self.is_capture_paused() {
+                    let paused_label = match self.pause_remaining_secs() {
+                        Some(secs) => format!("Paused ({} min)", secs.div_ceil(60)),
+                        None => "Paused".to_string(),
+                    };
+                    ui.label(
+                        egui::RichText::new(paused_label)
+                            .small()
+                            .strong()
+                            .color(ui.visuals().warn_fg_color),
+                    )
+                    .on_hover_text("Clipboard capture is paused, new copies are not recorded.");
+                }
                 ui.with_layout(egui::Layout::right_to_left(egui::Align::Center), |ui| {
                     let theme_icon = if self.config.dark_mode {
                         "🔆"
@@ -178,6 +214,16 @@ impl eframe::App for ClippoApp {
                             self.toggle_config_field("enable_search");
                         }
 
+                        if ui
+                            .checkbox(
+                                &mut self.config.search_sensitive_entries,
+                                "Search sensitive entries",
+                            )
+                            .clicked()
+                        {
+                            self.toggle_config_field("search_sensitive_entries");
+                        }
+
                         if ui
                             .add(
                                 egui::Slider::new(
@@ -191,6 +237,44 @@ impl eframe::App for ClippoApp {
                             self.toggle_config_field("max_entry_display_length");
                         }
                     });
+
+                    let capture_paused = self.is_capture_paused();
+                    let (pause_icon, pause_hint) = if capture_paused {
+                        ("▶", "Resume recording")
+                    } else {
+                        ("⏸", "Pause recording (right click for a timed pause)")
+                    };
+                    let pause_button = ui.button(pause_icon).on_hover_text(pause_hint);
+                    if pause_button.clicked() {
+                        let toggle_result = if capture_paused {
+                            self.resume_capture()
+                        } else {
+                            self.pause_capture(None)
+                        };
+                        match toggle_result {
+                            Ok(()) if capture_paused => self.set_last_action("Recording resumed."),
+                            Ok(()) => self.set_last_action("Recording paused."),
+                            Err(error) => {
+                                tracing::error!("Could not toggle capture in daemon: {error}");
+                                self.set_last_action("Failed to reach the clipboard daemon.");
+                            }
+                        }
+                    }
+                    pause_button.context_menu(|ui| {
+                        for (label, secs) in
+                            [("Pause for 5 minutes", 300), ("Pause for 1 hour", 3600)]
+                        {
+                            if ui.button(label).clicked() {
+                                if let Err(error) = self.pause_capture(Some(secs)) {
+                                    tracing::error!("Could not pause capture in daemon: {error}");
+                                    self.set_last_action("Failed to reach the clipboard daemon.");
+                                } else {
+                                    self.set_last_action("Recording paused.");
+                                }
+                                ui.close_menu();
+                            }
+                        }
+                    });
                 });
             });
             ui.add_space(2.0);
@@ -262,7 +346,9 @@ impl eframe::App for ClippoApp {
             // Main content
             egui::ScrollArea::vertical().show(ui, |ui| {
                 if filtered_history.is_empty() {
-                    let message = if total_entries == 0 {
+                    let message = if !daemon_connected {
+                        "Not connected to the clipboard daemon, retrying ..."
+                    } else if total_entries == 0 {
                         "Clipboard history is empty."
                     } else {
                         "No entries match your search."
@@ -274,16 +360,19 @@ impl eframe::App for ClippoApp {
                     return;
                 }
 
-                for (idx, value) in filtered_history.iter().enumerate() {
-                    let preview = self.preview_entry(value);
-                    let metadata = match value {
+                let mut revealed_entry_id = None;
+                for (idx, item) in filtered_history.iter().enumerate() {
+                    let revealed = self.revealed_entry_id == Some(item.id);
+                    let preview = item.preview(self.config.max_entry_display_length, revealed);
+                    let metadata = match &item.entry {
+                        _ if item.sensitive && !revealed => "Sensitive".to_string(),
                         ClipboardHistoryEntry::Text(text) => {
                             let chars = text.chars().count();
                             let lines = text.lines().count().max(1);
                             format!("{chars} chars, {lines} lines")
                         }
                         ClipboardHistoryEntry::Image(image) => {
-                            let kb = image.bytes.len() / 1024;
+                            let kb = image.expected_len() / 1024;
                             format!("{}x{}, {} KB", image.width, image.height, kb)
                         }
                     };
@@ -296,6 +385,7 @@ impl eframe::App for ClippoApp {
                         entry_frame = entry_frame.fill(selection.bg_fill).stroke(selection.stroke);
                     }
 
+                    let mut reveal_rect = None;
                     let entry = entry_frame.show(ui, |ui| {
                         ui.vertical(|ui| {
                             ui.add(
@@ -313,6 +403,14 @@ impl eframe::App for ClippoApp {
                                         ui.label(
                                             egui::RichText::new("Click or Enter to copy").small(),
                                         );
+                                        if item.sensitive {
+                                            // Plain label, the press is read from the entry overlay below
+                                            reveal_rect = Some(
+                                                ui.label(egui::RichText::new("👁").strong())
+                                                    .on_hover_text("Hold to reveal")
+                                                    .rect,
+                                            );
+                                        }
                                     },
                                 );
                             });
@@ -326,8 +424,33 @@ impl eframe::App for ClippoApp {
                         ui.id().with(("history_entry", idx)),
                         egui::Sense::click(),
                     );
-                    let clicked = clickable.clicked();
+                    let pointer_on_reveal = reveal_rect.is_some_and(|rect| {
+                        ctx.input(|input| input.pointer.interact_pos())
+                            .is_some_and(|pos| rect.contains(pos))
+                    });
+                    if pointer_on_reveal && clickable.is_pointer_button_down_on() {
+                        revealed_entry_id = Some(item.id);
+                    }
+                    let clicked = clickable.clicked() && !pointer_on_reveal;
                     let hovered = clickable.hovered();
+                    clickable.context_menu(|ui| {
+                        let (label, sensitive) = if item.sensitive {
+                            ("Unmark as sensitive", false)
+                        } else {
+                            ("Mark as sensitive", true)
+                        };
+                        if ui.button(label).clicked() {
+                            if let Err(error) = self.set_entry_sensitive(item.id, sensitive) {
+                                tracing::error!("Could not update sensitive flag: {error}");
+                                self.set_last_action("Failed to update entry.");
+                            } else if sensitive {
+                                self.set_last_action("Entry marked as sensitive.");
+                            } else {
+                                self.set_last_action("Entry unmarked as sensitive.");
+                            }
+                            ui.close_menu();
+                        }
+                    });
                     clickable.on_hover_cursor(egui::CursorIcon::PointingHand);
                     if hovered && !is_selected {
                         let hover_style = ui.visuals().widgets.hovered;
@@ -340,7 +463,7 @@ impl eframe::App for ClippoApp {
                     }
                     if clicked {
                         self.selected_entry_index = Some(idx);
-                        if let Err(error) = self.copy_to_clipboard(value) {
+                        if let Err(error) = self.copy_to_clipboard(item.id) {
                             tracing::error!("Could not set clipboard value on click: {error:#}");
                             self.set_last_action("Failed to copy entry to clipboard.");
                         } else {
@@ -355,13 +478,18 @@ impl eframe::App for ClippoApp {
                     }
                     ui.add_space(6.0);
                 }
+
+                if revealed_entry_id != self.revealed_entry_id {
+                    self.revealed_entry_id = revealed_entry_id;
+                    ctx.request_repaint();
+                }
             });
         });
 
         let action_is_expired = self
             .last_action
             .as_ref()
-            .map(|(_, at)| at.elapsed() > Duration::from_secs(4))
+            .map(|(_, at)| at.elapsed() > Duration::from_secs(LAST_ACTION_DISPLAY_SECS))
             .unwrap_or(false);
         if action_is_expired {
             self.last_action = None;
@@ -388,11 +516,81 @@ impl eframe::App for ClippoApp {
             ui.add_space(3.0);
         });
 
-        // Poll daemon updates without forcing full-speed repainting.
-        if self.last_action.is_some() {
-            ctx.request_repaint_after(Duration::from_millis(100));
-        } else {
-            ctx.request_repaint_after(Duration::from_millis(250));
+        // Daemon updates request their own repaint, only time based changes are scheduled here
+        if let Some((_, at)) = &self.last_action {
+            ctx.request_repaint_after(
+                Duration::from_secs(LAST_ACTION_DISPLAY_SECS).saturating_sub(at.elapsed()),
+            );
+        }
+        if let Some(secs) = self.pause_remaining_secs() {
+            // The remaining time is shown in minutes, and the pause may end
+            ctx.request_repaint_after(Duration::from_secs(secs % 60 + 1));
+        }
+    }
+}
+
+/// Dot colored after the daemon state, with its status on hover.
+/// Without a history subscription, the daemon is shown as disconnected.
+fn daemon_indicator(ui: &mut egui::Ui, connected: bool, status: Option<&DaemonStatus>) {
+    if !connected {
+        ui.label(
+            egui::RichText::new("● Disconnected")
+                .small()
+                .strong()
+                .color(ui.visuals().error_fg_color),
+        )
+        .on_hover_text(
+            "The clipboard daemon can not be reached, the history shown may be outdated. \
+             Reconnecting ...",
+        );
+        return;
+    }
+
+    let Some(status) = status else {
+        ui.label(egui::RichText::new("●").weak())
+            .on_hover_text("Connected to the clipboard daemon, waiting for its status ...");
+        return;
+    };
+
+    let (color, state) = match status.capture_state {
+        CaptureState::Recording if status.recent_errors.is_empty() => {
+            (egui::Color32::from_rgb(80, 180, 90), "recording")
+        }
+        CaptureState::Recording => (ui.visuals().warn_fg_color, "recording, with errors"),
+        CaptureState::Paused(_) => (ui.visuals().warn_fg_color, "paused"),
+    };
+
+    let last_capture = match status.last_capture_at {
+        Some(at) => format!("{} ago", format_duration(unix_now().saturating_sub(at))),
+        None => "none since start".to_string(),
+    };
+    let mut details = format!(
+        "Clippo daemon {}, {state}\n\
+         Up for {}, using {}\n\
+         {} text and {} image entries, {}\n\
+         Last capture: {last_capture}\n\
+         History: {}",
+        status.version,
+        format_duration(status.uptime_secs),
+        status.backend,
+        status.text_entries,
+        status.image_entries,
+        format_size(status.total_bytes),
+        status.history_path,
+    );
+    if !status.recent_errors.is_empty() {
+        details.push_str("\n\nRecent errors:");
+        // Most recent first, the full list is available from the status request
+        for error in status.recent_errors.iter().rev().take(5) {
+            let age = unix_now().saturating_sub(error.at);
+            details.push_str(&format!(
+                "\n{} ago: {}",
+                format_duration(age),
+                error.message
+            ));
         }
     }
+
+    ui.label(egui::RichText::new("●").color(color))
+        .on_hover_text(details);
 }